@@ -0,0 +1,174 @@
+use crate::token::Span;
+use std::fmt;
+use std::str::FromStr;
+
+// ErrorKind enumerates the Excel error values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    Null,
+    Div0,
+    Value,
+    Ref,
+    Name,
+    Num,
+    NA,
+    Spill,
+    Calc,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 9] = [
+        ErrorKind::Null,
+        ErrorKind::Div0,
+        ErrorKind::Value,
+        ErrorKind::Ref,
+        ErrorKind::Name,
+        ErrorKind::Num,
+        ErrorKind::NA,
+        ErrorKind::Spill,
+        ErrorKind::Calc,
+    ];
+
+    // as_str return the text Excel displays for the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Null => "#NULL!",
+            ErrorKind::Div0 => "#DIV/0!",
+            ErrorKind::Value => "#VALUE!",
+            ErrorKind::Ref => "#REF!",
+            ErrorKind::Name => "#NAME?",
+            ErrorKind::Num => "#NUM!",
+            ErrorKind::NA => "#N/A",
+            ErrorKind::Spill => "#SPILL!",
+            ErrorKind::Calc => "#CALC!",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorKind {
+    type Err = ();
+
+    // from_str parses the display text of an error, ignoring case.
+    fn from_str(s: &str) -> Result<ErrorKind, ()> {
+        ErrorKind::ALL
+            .iter()
+            .find(|k| k.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or(())
+    }
+}
+
+// ErrorValue is the payload of `Value::Error`: an error kind with an optional
+// diagnostic message for the host. Like in Excel, two errors are equal when they
+// are of the same kind, whatever their messages.
+#[derive(Debug, Clone)]
+pub struct ErrorValue {
+    pub kind: ErrorKind,
+    pub message: Option<String>,
+}
+
+impl ErrorValue {
+    pub fn new(kind: ErrorKind) -> ErrorValue {
+        ErrorValue {
+            kind,
+            message: None,
+        }
+    }
+
+    pub fn with_message(kind: ErrorKind, message: impl Into<String>) -> ErrorValue {
+        ErrorValue {
+            kind,
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl PartialEq for ErrorValue {
+    fn eq(&self, other: &ErrorValue) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<ErrorKind> for ErrorValue {
+    fn from(kind: ErrorKind) -> ErrorValue {
+        ErrorValue::new(kind)
+    }
+}
+
+impl fmt::Display for ErrorValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnbalancedParen,
+    UnexpectedToken,
+    UnterminatedString,
+    DanglingOperator,
+    UnknownFunction,
+    ArgumentCount,
+    UnsupportedOperator,
+    RaggedArray,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ParseErrorKind::UnbalancedParen => "unbalanced parenthesis",
+            ParseErrorKind::UnexpectedToken => "unexpected token",
+            ParseErrorKind::UnterminatedString => "unterminated string",
+            ParseErrorKind::DanglingOperator => "operator is missing an operand",
+            ParseErrorKind::UnknownFunction => "unknown function",
+            ParseErrorKind::ArgumentCount => "wrong number of arguments",
+            ParseErrorKind::UnsupportedOperator => "unsupported operator",
+            ParseErrorKind::RaggedArray => "array rows have different lengths",
+        };
+        f.write_str(s)
+    }
+}
+
+// ParseError reports why a formula could not be parsed, and where. `span` holds
+// byte offsets and `char_span` char offsets into the formula given to the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    pub char_span: Span,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span, formula: &str) -> ParseError {
+        ParseError {
+            kind,
+            span,
+            char_span: span.to_chars(formula),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.kind, self.char_span.start, self.char_span.end
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(feature = "wasm")]
+impl From<ParseError> for wasm_bindgen::JsValue {
+    fn from(e: ParseError) -> wasm_bindgen::JsValue {
+        wasm_bindgen::JsValue::from_str(&e.to_string())
+    }
+}
@@ -1,71 +1,175 @@
-use crate::exp::Expression;
+use crate::error::{ErrorKind, ErrorValue};
+use crate::exp::{BinaryOp, Expression, UnaryOp};
 use crate::functions;
+use crate::reference::{CellRef, Coord, RangeRef, Resolver, MAX_COLUMN, MAX_ROW};
+use crate::registry::FunctionRegistry;
 use crate::value::Value;
 use std::collections::HashMap;
 
+// Spill is the result of a formula held by a cell of a grid. An array result
+// spills over the cells right and below, which must be empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spill {
+    // the result, or #SPILL! when the array cannot spill, #CALC! when it is
+    // empty and #REF! when the anchor does not locate a cell
+    pub value: Value,
+    // the cells an array result covers, the anchor cell first; None for a single
+    // value or an array which does not fit in the sheet
+    pub extent: Option<RangeRef>,
+    // the occupied cells which block the spill
+    pub blocked: Vec<CellRef>,
+}
+
+impl Spill {
+    // unspilled return the spill of a value covering no cells.
+    fn unspilled(value: Value) -> Spill {
+        Spill {
+            value,
+            extent: None,
+            blocked: vec![],
+        }
+    }
+}
+
 pub struct Evaluator<'a> {
     pub expr: Expression,
     pub context: &'a HashMap<String, Value>,
+    pub functions: &'a FunctionRegistry,
+    // supplies the cells references point to; without one they are #REF!, as
+    // are R1C1 references not converted to A1 with `Expression::to_a1`
+    pub resolver: Option<&'a dyn Resolver>,
 }
 
 impl<'a> Evaluator<'a> {
     pub fn resolve(&self) -> Value {
-        self._resolve(&self.expr)
+        self.evaluate(&self.expr)
     }
 
-    fn _resolve(&self, e: &Expression) -> Value {
+    // resolve_spill resolves the formula held by the anchor cell, spilling an
+    // array result over the cells for which occupied is false.
+    pub fn resolve_spill(&self, anchor: &CellRef, occupied: impl Fn(&CellRef) -> bool) -> Spill {
+        if !anchor.is_anchored() {
+            return Spill::unspilled(Value::Error(ErrorKind::Ref.into()));
+        }
+        let value = self.resolve();
+        let array = match &value {
+            Value::Array(a) if a.rows == 0 || a.cols == 0 => {
+                return Spill::unspilled(Value::Error(ErrorValue::with_message(
+                    ErrorKind::Calc,
+                    "array result is empty",
+                )))
+            }
+            Value::Array(a) => a,
+            _ => return Spill::unspilled(value),
+        };
+        let (row, col) = (anchor.row.index(), anchor.col.index());
+        let last_row = row as usize + array.rows - 1;
+        let last_col = col as usize + array.cols - 1;
+        if last_row > MAX_ROW as usize || last_col > MAX_COLUMN as usize {
+            return Spill::unspilled(Value::Error(ErrorValue::with_message(
+                ErrorKind::Spill,
+                "spill range extends beyond the sheet",
+            )));
+        }
+        let cell = |row: usize, col: usize| CellRef {
+            row: Coord::Relative(row as u32),
+            col: Coord::Relative(col as u32),
+        };
+        let mut blocked = vec![];
+        for r in row as usize..=last_row {
+            for c in col as usize..=last_col {
+                let cell = cell(r, c);
+                if (r, c) != (row as usize, col as usize) && occupied(&cell) {
+                    blocked.push(cell);
+                }
+            }
+        }
+        let extent = Some(RangeRef::Area(
+            cell(row as usize, col as usize),
+            cell(last_row, last_col),
+        ));
+        if !blocked.is_empty() {
+            return Spill {
+                value: Value::Error(ErrorValue::with_message(
+                    ErrorKind::Spill,
+                    format!("spill range is blocked by {}", blocked[0]),
+                )),
+                extent,
+                blocked,
+            };
+        }
+        Spill {
+            value,
+            extent,
+            blocked,
+        }
+    }
+
+    // evaluate return the value of e, a node of the expression being resolved.
+    pub fn evaluate(&self, e: &Expression) -> Value {
         match &e {
-            Expression::Literal(ref v) => v.clone(),
-            Expression::Operator { left, op, right } => {
-                let lhs = self._resolve(&**left);
-                match &**right {
-                    None => lhs,
-                    Some(r) => {
-                        let rhs = self._resolve(r);
-                        let op_str = op.as_ref().unwrap().as_str();
-                        match op_str {
-                            "+" => functions::add(&lhs, &rhs),
-                            "-" => functions::subtract(&lhs, &rhs),
-                            "*" => functions::multiply(&lhs, &rhs),
-                            "/" => functions::divide(&lhs, &rhs),
-                            ">" | ">=" | "<" | "<=" | "<>" | "=" | "==" => {
-                                functions::compare(&lhs, &rhs, op_str)
-                            }
-                            // rcompare(left, right, ope.Op)
-                            _ => functions::sum(&[lhs, rhs]),
-                        }
-                    }
+            Expression::Literal { value, .. } => value.clone(),
+            Expression::Reference {
+                sheet, reference, ..
+            } => match self.resolver {
+                Some(r) if reference.is_anchored() => r.cell(sheet.as_ref(), reference),
+                _ => Value::Error(ErrorKind::Ref.into()),
+            },
+            Expression::Range { sheet, range, .. } => match self.resolver {
+                Some(r) if range.is_anchored() => r.range(sheet.as_ref(), range),
+                _ => Value::Error(ErrorKind::Ref.into()),
+            },
+            Expression::SpillRange { sheet, anchor, .. } => match self.resolver {
+                Some(r) if anchor.is_anchored() => r.spill(sheet.as_ref(), anchor),
+                _ => Value::Error(ErrorKind::Ref.into()),
+            },
+            Expression::Table { reference, .. } => match self.resolver {
+                Some(r) => r.table(reference),
+                None => Value::Error(ErrorKind::Ref.into()),
+            },
+            Expression::Unary { op, operand, .. } => {
+                let v = self.evaluate(operand);
+                match op {
+                    UnaryOp::Negate => functions::map(&v, functions::negate),
+                    UnaryOp::Plus => v,
+                    UnaryOp::Percent => functions::map(&v, functions::percent),
                 }
             }
+            Expression::Binary {
+                left, op, right, ..
+            } => {
+                let lhs = self.evaluate(left);
+                let rhs = self.evaluate(right);
+                functions::broadcast(&lhs, &rhs, |l, r| match op {
+                    BinaryOp::Add => functions::add(l, r),
+                    BinaryOp::Subtract => functions::subtract(l, r),
+                    BinaryOp::Multiply => functions::multiply(l, r),
+                    BinaryOp::Divide => functions::divide(l, r),
+                    BinaryOp::Power => functions::power(l, r),
+                    BinaryOp::Concat => functions::concat(l, r),
+                    BinaryOp::Equal
+                    | BinaryOp::NotEqual
+                    | BinaryOp::Less
+                    | BinaryOp::LessEqual
+                    | BinaryOp::Greater
+                    | BinaryOp::GreaterEqual => functions::compare(l, r, *op),
+                })
+            }
             Expression::Function {
                 function,
                 arguments,
-            } => {
-                let mut args: Vec<Value> = vec![];
-                for arg in arguments.iter() {
-                    let v = self._resolve(arg);
-                    if v.is_error() {
-                        return v;
-                    }
-                    args.push(v)
-                }
-
-                let func = get_function(self.context, function);
-                func(&args)
-            }
+                ..
+            } => match self.functions.get(function) {
+                Some(f) if !f.accepts(arguments.len()) => Value::Error(ErrorValue::with_message(
+                    ErrorKind::Value,
+                    format!("wrong number of arguments to {}", f.name),
+                )),
+                Some(f) => f.call(arguments, self),
+                None => Value::Error(ErrorValue::with_message(
+                    ErrorKind::Name,
+                    format!("unknown function {}", function),
+                )),
+            },
         }
     }
 }
-
-fn get_function<'a>(
-    context: &HashMap<String, Value>,
-    name: &str,
-) -> Box<dyn Fn(&'a [Value]) -> Value> {
-    match name {
-        "SUM" => Box::new(functions::sum),
-        "TODAY" => Box::new(functions::today),
-        "REF" => functions::get_ref_value(context.clone()),
-        "AVERAGE" => Box::new(functions::avarage),
-        _ => Box::new(functions::sum),
-    }
-}
@@ -1,22 +1,293 @@
+use crate::error::ErrorKind;
+use crate::reference::{CellRef, RangeRef, ReferenceStyle, SheetRef, TableRef};
+use crate::token::Span;
 use crate::value::Value;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    // prefix -
+    Negate,
+    // prefix +, which leaves its operand untouched
+    Plus,
+    // postfix %, dividing its operand by 100
+    Percent,
+}
+
+impl UnaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::Percent => "%",
+        }
+    }
+
+    pub fn is_postfix(&self) -> bool {
+        *self == UnaryOp::Percent
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Concat,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl BinaryOp {
+    // parse return the operator written as s, if any.
+    pub fn parse(s: &str) -> Option<BinaryOp> {
+        match s {
+            "+" => Some(BinaryOp::Add),
+            "-" => Some(BinaryOp::Subtract),
+            "*" => Some(BinaryOp::Multiply),
+            "/" => Some(BinaryOp::Divide),
+            "^" => Some(BinaryOp::Power),
+            "&" => Some(BinaryOp::Concat),
+            "=" | "==" => Some(BinaryOp::Equal),
+            "<>" => Some(BinaryOp::NotEqual),
+            "<" => Some(BinaryOp::Less),
+            "<=" => Some(BinaryOp::LessEqual),
+            ">" => Some(BinaryOp::Greater),
+            ">=" => Some(BinaryOp::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Power => "^",
+            BinaryOp::Concat => "&",
+            BinaryOp::Equal => "=",
+            BinaryOp::NotEqual => "<>",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+        }
+    }
+
+    // precedence is higher for operators binding tighter; all are left-associative.
+    pub fn precedence(&self) -> i8 {
+        match self {
+            BinaryOp::Power => 50,
+            BinaryOp::Multiply | BinaryOp::Divide => 40,
+            BinaryOp::Add | BinaryOp::Subtract => 30,
+            BinaryOp::Concat => 20,
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Less
+            | BinaryOp::LessEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual => 10,
+        }
+    }
+}
+
+// every node carries the span of the formula text it was parsed from.
 #[derive(Debug)]
 pub enum Expression {
-    Literal(Value),
-    Operator {
-        op: Option<String>,
+    Literal {
+        value: Value,
+        span: Span,
+    },
+    Reference {
+        sheet: Option<SheetRef>,
+        reference: CellRef,
+        // the notation the reference is written in
+        style: ReferenceStyle,
+        span: Span,
+    },
+    Range {
+        sheet: Option<SheetRef>,
+        range: RangeRef,
+        style: ReferenceStyle,
+        span: Span,
+    },
+    // A1#, the whole array spilled from the anchor cell
+    SpillRange {
+        sheet: Option<SheetRef>,
+        anchor: CellRef,
+        style: ReferenceStyle,
+        span: Span,
+    },
+    Table {
+        reference: TableRef,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expression>,
+        span: Span,
+    },
+    Binary {
+        op: BinaryOp,
         left: Box<Expression>,
-        right: Box<Option<Expression>>,
+        right: Box<Expression>,
+        span: Span,
     },
     Function {
         function: String,
         arguments: Vec<Expression>,
+        span: Span,
     },
 }
 
 impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Literal { span, .. }
+            | Expression::Reference { span, .. }
+            | Expression::Range { span, .. }
+            | Expression::SpillRange { span, .. }
+            | Expression::Table { span, .. }
+            | Expression::Unary { span, .. }
+            | Expression::Binary { span, .. }
+            | Expression::Function { span, .. } => *span,
+        }
+    }
+
+    // to_r1c1 rewrites the relative references of the expression into R1C1
+    // offsets from the anchor, the cell holding the formula.
+    pub fn to_r1c1(self, anchor: &CellRef) -> Expression {
+        self.map_references(&|e| match e {
+            Expression::Reference {
+                sheet,
+                reference,
+                span,
+                ..
+            } => Expression::Reference {
+                sheet,
+                reference: reference.to_r1c1(anchor),
+                style: ReferenceStyle::R1C1,
+                span,
+            },
+            Expression::Range {
+                sheet, range, span, ..
+            } => Expression::Range {
+                sheet,
+                range: range.to_r1c1(anchor),
+                style: ReferenceStyle::R1C1,
+                span,
+            },
+            Expression::SpillRange {
+                sheet,
+                anchor: reference,
+                span,
+                ..
+            } => Expression::SpillRange {
+                sheet,
+                anchor: reference.to_r1c1(anchor),
+                style: ReferenceStyle::R1C1,
+                span,
+            },
+            e => e,
+        })
+    }
+
+    // to_a1 rewrites the R1C1 offsets of the expression into relative references
+    // from the anchor. References falling outside of the sheet become #REF!.
+    pub fn to_a1(self, anchor: &CellRef) -> Expression {
+        self.map_references(&|e| match e {
+            Expression::Reference {
+                sheet,
+                reference,
+                span,
+                ..
+            } => match reference.to_a1(anchor) {
+                Some(reference) => Expression::Reference {
+                    sheet,
+                    reference,
+                    style: ReferenceStyle::A1,
+                    span,
+                },
+                None => Expression::Literal {
+                    value: Value::Error(ErrorKind::Ref.into()),
+                    span,
+                },
+            },
+            Expression::Range {
+                sheet, range, span, ..
+            } => match range.to_a1(anchor) {
+                Some(range) => Expression::Range {
+                    sheet,
+                    range,
+                    style: ReferenceStyle::A1,
+                    span,
+                },
+                None => Expression::Literal {
+                    value: Value::Error(ErrorKind::Ref.into()),
+                    span,
+                },
+            },
+            Expression::SpillRange {
+                sheet,
+                anchor: reference,
+                span,
+                ..
+            } => match reference.to_a1(anchor) {
+                Some(reference) => Expression::SpillRange {
+                    sheet,
+                    anchor: reference,
+                    style: ReferenceStyle::A1,
+                    span,
+                },
+                None => Expression::Literal {
+                    value: Value::Error(ErrorKind::Ref.into()),
+                    span,
+                },
+            },
+            e => e,
+        })
+    }
+
+    // map_references return the expression with f applied to its leaves.
+    fn map_references(self, f: &dyn Fn(Expression) -> Expression) -> Expression {
+        match self {
+            Expression::Unary { op, operand, span } => Expression::Unary {
+                op,
+                operand: Box::new(operand.map_references(f)),
+                span,
+            },
+            Expression::Binary {
+                op,
+                left,
+                right,
+                span,
+            } => Expression::Binary {
+                op,
+                left: Box::new(left.map_references(f)),
+                right: Box::new(right.map_references(f)),
+                span,
+            },
+            Expression::Function {
+                function,
+                arguments,
+                span,
+            } => Expression::Function {
+                function,
+                arguments: arguments.into_iter().map(|a| a.map_references(f)).collect(),
+                span,
+            },
+            e => f(e),
+        }
+    }
+
     /*  pub fn valid(&self) -> bool {
-        return match self {
+        match self {
             Expression::Literal(ref v) => match v {
                 Value::Error(_) => true,
                 _ => false,
@@ -28,32 +299,112 @@ impl Expression {
 }
 
 pub fn get_precedence(op: &str) -> i8 {
-    match op {
-        "/" => 40,
-        "*" => 40,
-        "+" => 30,
-        "-" => 30,
-        "&" => 20,
-        "<" => 10,
-        ">" => 10,
-        "=" => 10,
-        "==" => 10,
-        "<=" => 10,
-        ">=" => 10,
-        "<>" => 10,
-        _ => -1,
+    BinaryOp::parse(op).map_or(-1, |op| op.precedence())
+}
+
+// operand_needs_parens return whether e must be enclosed in parentheses as the
+// operand of op, so that the formula text parses back into the same tree.
+fn operand_needs_parens(op: UnaryOp, e: &Expression) -> bool {
+    match e {
+        Expression::Binary { .. } => true,
+        Expression::Unary { op: inner, .. } => !op.is_postfix() && inner.is_postfix(),
+        _ => false,
     }
 }
 
+// side_needs_parens return whether e must be enclosed in parentheses as the left
+// or right side of op, all operators being left-associative.
+fn side_needs_parens(op: BinaryOp, e: &Expression, right: bool) -> bool {
+    match e {
+        Expression::Binary { op: inner, .. } if right => inner.precedence() <= op.precedence(),
+        Expression::Binary { op: inner, .. } => inner.precedence() < op.precedence(),
+        _ => false,
+    }
+}
+
+// Expressions display as formula text, without the leading "=".
 impl std::fmt::Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return match self {
-            Expression::Literal(ref v) => write!(f, "{:?}", v.as_string()),
-            Expression::Operator { left, op, right } => write!(f, "{}{:?}{:?}", left, op, right),
+        let parens = |e: &Expression, enclose: bool| {
+            if enclose {
+                format!("({})", e)
+            } else {
+                e.to_string()
+            }
+        };
+        match self {
+            Expression::Literal { value, .. } => match value {
+                Value::String(s) => write!(f, "\"{}\"", s.replace('"', "\"\"")),
+                Value::Error(e) => write!(f, "{}", e),
+                Value::Array(a) => write!(f, "{}", a),
+                Value::Unknown => Ok(()),
+                v => f.write_str(&v.to_text().unwrap_or_default()),
+            },
+            Expression::Reference {
+                sheet,
+                reference,
+                style,
+                ..
+            } => {
+                if let Some(sheet) = sheet {
+                    write!(f, "{}", sheet)?;
+                }
+                write!(f, "{}", reference.display(*style))
+            }
+            Expression::Range {
+                sheet,
+                range,
+                style,
+                ..
+            } => {
+                if let Some(sheet) = sheet {
+                    write!(f, "{}", sheet)?;
+                }
+                write!(f, "{}", range.display(*style))
+            }
+            Expression::SpillRange {
+                sheet,
+                anchor,
+                style,
+                ..
+            } => {
+                if let Some(sheet) = sheet {
+                    write!(f, "{}", sheet)?;
+                }
+                write!(f, "{}#", anchor.display(*style))
+            }
+            Expression::Table { reference, .. } => write!(f, "{}", reference),
+            Expression::Unary { op, operand, .. } => {
+                let operand = parens(operand, operand_needs_parens(*op, operand));
+                if op.is_postfix() {
+                    write!(f, "{}{}", operand, op.as_str())
+                } else {
+                    write!(f, "{}{}", op.as_str(), operand)
+                }
+            }
+            Expression::Binary {
+                left, op, right, ..
+            } => write!(
+                f,
+                "{}{}{}",
+                parens(left, side_needs_parens(*op, left, false)),
+                op.as_str(),
+                parens(right, side_needs_parens(*op, right, true))
+            ),
             Expression::Function {
                 function,
                 arguments,
-            } => write!(f, "{}{:?}", function, arguments),
-        };
+                ..
+            } => {
+                write!(f, "{}(", function)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                f.write_str(")")
+            }
+        }
     }
 }
@@ -1,10 +1,22 @@
+use crate::error::{ErrorKind, ErrorValue};
+use crate::eval::Evaluator;
+use crate::exp::{BinaryOp, Expression};
 use crate::oadate;
-use crate::value::Value;
+use crate::reference::{MAX_COLUMN, MAX_ROW};
+use crate::value::{Array, Value};
 
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
 
 const GUID_LENGTH: usize = 11;
 
+// MAX_ARRAY_CELLS is the largest array the array building functions return, a
+// full column, so that a formula cannot exhaust the memory of the host.
+pub const MAX_ARRAY_CELLS: usize = MAX_ROW as usize;
+
 /* pub enum CriterionType {
     Equal,
     NotEqual,
@@ -14,13 +26,81 @@ const GUID_LENGTH: usize = 11;
     LessOrEqual,
 } */
 
+// arithmetic applies f to both operands once coerced to numbers, the left
+// operand's error winning over the right one's.
+fn arithmetic(left: &Value, right: &Value, f: impl Fn(f64, f64) -> Value) -> Value {
+    match (left.coerce_number(), right.coerce_number()) {
+        (Ok(l), Ok(r)) => f(l, r),
+        (Err(e), _) | (_, Err(e)) => Value::Error(e),
+    }
+}
+
+// number wraps n, reporting #NUM! when it overflowed.
+fn number(n: f64) -> Value {
+    if n.is_finite() {
+        Value::Number(n)
+    } else {
+        Value::Error(ErrorKind::Num.into())
+    }
+}
+
+// broadcast applies the binary operator f to the operands item by item when
+// either is an array, like Excel does: the result is as large as the largest
+// operand, a single row or column being repeated to fit and a scalar acting as a
+// 1x1 array. Items past the end of a smaller operand are #N/A.
+pub fn broadcast(left: &Value, right: &Value, f: impl Fn(&Value, &Value) -> Value) -> Value {
+    let (l, r) = match (left, right) {
+        (Value::Array(l), Value::Array(r)) => (l.clone(), r.clone()),
+        (Value::Array(l), r) => (l.clone(), Array::row(vec![r.clone()])),
+        (l, Value::Array(r)) => (Array::row(vec![l.clone()]), r.clone()),
+        (l, r) => return f(l, r),
+    };
+    let (rows, cols) = (l.rows.max(r.rows), l.cols.max(r.cols));
+    let item = |a: &Array, row: usize, col: usize| {
+        let row = if a.rows == 1 { 0 } else { row };
+        let col = if a.cols == 1 { 0 } else { col };
+        a.get(row, col)
+            .cloned()
+            .unwrap_or_else(|| Value::Error(ErrorKind::NA.into()))
+    };
+    let mut items = Vec::with_capacity(rows * cols);
+    for row in 0..rows {
+        for col in 0..cols {
+            items.push(f(&item(&l, row, col), &item(&r, row, col)));
+        }
+    }
+    Value::Array(Array::new(rows, cols, items))
+}
+
+// map applies the unary operator f to v, or to each of its items if it is an
+// array.
+pub fn map(v: &Value, f: impl Fn(&Value) -> Value) -> Value {
+    match v {
+        Value::Array(a) => {
+            Value::Array(Array::new(a.rows, a.cols, a.items.iter().map(f).collect()))
+        }
+        v => f(v),
+    }
+}
+
 pub fn add(left: &Value, right: &Value) -> Value {
-    Value::Number(left.to_number() + right.to_number())
+    arithmetic(left, right, |l, r| number(l + r))
+}
+
+// flatten iterates over args, expanding arrays to their items.
+fn flatten(args: &[Value]) -> impl Iterator<Item = &Value> {
+    args.iter().flat_map(|v| match v {
+        Value::Array(a) => a.items.iter(),
+        _ => std::slice::from_ref(v).iter(),
+    })
 }
 
 pub fn sum(args: &[Value]) -> Value {
     let mut result = 0f64;
-    for v in args {
+    for v in flatten(args) {
+        if v.is_error() {
+            return v.clone();
+        }
         result += v.to_number();
     }
     Value::Number(result)
@@ -28,7 +108,7 @@ pub fn sum(args: &[Value]) -> Value {
 
 pub fn count(args: &[Value]) -> Value {
     let mut cnt = 0.0;
-    for v in args {
+    for v in flatten(args) {
         if v.is_number() {
             cnt += 1.0;
         }
@@ -37,54 +117,609 @@ pub fn count(args: &[Value]) -> Value {
 }
 
 pub fn avarage(args: &[Value]) -> Value {
+    let total = match sum(args) {
+        Value::Number(total) => total,
+        e => return e,
+    };
     let cnt = count(args).to_number();
     if cnt == 0.0 {
         return Value::Empty;
     }
-    Value::Number(sum(args).to_number() / cnt)
+    Value::Number(total / cnt)
 }
 
 pub fn subtract(left: &Value, right: &Value) -> Value {
-    Value::Number(left.to_number() - right.to_number())
+    arithmetic(left, right, |l, r| number(l - r))
 }
 
 pub fn multiply(left: &Value, right: &Value) -> Value {
-    Value::Number(left.to_number() * right.to_number())
+    arithmetic(left, right, |l, r| number(l * r))
 }
+
 pub fn divide(left: &Value, right: &Value) -> Value {
-    if right.to_number() == 0f64 {
-        return Value::Error("#DIV/0".into());
+    arithmetic(left, right, |l, r| {
+        if r == 0f64 {
+            return Value::Error(ErrorKind::Div0.into());
+        }
+        number(l / r)
+    })
+}
+
+// negate implements the prefix - operator.
+pub fn negate(v: &Value) -> Value {
+    match v.coerce_number() {
+        Ok(n) => Value::Number(-n),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// percent implements the postfix % operator.
+pub fn percent(v: &Value) -> Value {
+    match v.coerce_number() {
+        Ok(n) => number(n / 100.0),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// power implements the ^ operator and POWER(number, power).
+pub fn power(left: &Value, right: &Value) -> Value {
+    arithmetic(left, right, |l, r| {
+        if l == 0f64 && r == 0f64 {
+            return Value::Error(ErrorKind::Num.into());
+        }
+        if l == 0f64 && r < 0f64 {
+            return Value::Error(ErrorKind::Div0.into());
+        }
+        number(l.powf(r))
+    })
+}
+
+// concat implements the & operator.
+pub fn concat(left: &Value, right: &Value) -> Value {
+    match (left.to_text(), right.to_text()) {
+        (Ok(l), Ok(r)) => Value::String(l + &r),
+        (Err(e), _) | (_, Err(e)) => Value::Error(e),
     }
-    Value::Number(left.to_number() / right.to_number())
+}
+
+// CONCAT(text1, [text2], ...)
+// arrays and ranges are joined item by item.
+pub fn concat_all(args: &[Value]) -> Value {
+    let mut result = String::new();
+    for v in flatten(args) {
+        match v.to_text() {
+            Ok(s) => result += &s,
+            Err(e) => return Value::Error(e),
+        }
+    }
+    Value::String(result)
+}
+
+// CONCATENATE(text1, [text2], ...)
+// arrays are joined item by item, like with the & operator.
+pub fn concatenate(args: &[Value]) -> Value {
+    args.iter().fold(Value::String(String::new()), |result, v| {
+        broadcast(&result, v, concat)
+    })
 }
 
 pub fn today(_args: &[Value]) -> Value {
     Value::Number(oadate::today())
 }
 
-pub fn compare(left: &Value, right: &Value, op: &str) -> Value {
+pub fn compare(left: &Value, right: &Value, op: BinaryOp) -> Value {
+    if let Value::Error(e) = left {
+        return Value::Error(e.clone());
+    }
+    if let Value::Error(e) = right {
+        return Value::Error(e.clone());
+    }
+    let ord = cmp_values(left, right);
     match op {
-        "=" | "==" => Value::Boolean(left.to_number() - right.to_number() == 0.0),
-        ">" => Value::Boolean(left.to_number() - right.to_number() > 0.0),
-        ">=" => Value::Boolean(left.to_number() - right.to_number() >= 0.0),
-        "<" => Value::Boolean(left.to_number() - right.to_number() < 0.0),
-        "<=" => Value::Boolean(left.to_number() - right.to_number() <= 0.0),
-        "<>" => Value::Boolean(left.to_number() - right.to_number() != 0.0),
-        _ => Value::Boolean(false),
+        BinaryOp::Equal => Value::Boolean(ord == Ordering::Equal),
+        BinaryOp::Greater => Value::Boolean(ord == Ordering::Greater),
+        BinaryOp::GreaterEqual => Value::Boolean(ord != Ordering::Less),
+        BinaryOp::Less => Value::Boolean(ord == Ordering::Less),
+        BinaryOp::LessEqual => Value::Boolean(ord != Ordering::Greater),
+        BinaryOp::NotEqual => Value::Boolean(ord != Ordering::Equal),
+        _ => Value::Error(ErrorKind::Value.into()),
     }
 }
 
-// return values in eval context  key:value pairs, key should be like "11-byte-table-guid.11-byte-column-guid"
-pub fn get_ref_value(context: HashMap<String, Value>) -> Box<dyn Fn(&[Value]) -> Value> {
-    Box::new(move |val| {
-        if val.is_empty() {
-            return Value::Error("#REF!".to_string());
-        }
-        let ref_string = val.get(0).unwrap().as_string();
-        let (_, column_id) = ref_string.split_at(GUID_LENGTH + 1);
-        return match context.get(column_id) {
-            Some(arg) => arg.clone(),
-            _ => Value::Error("#REF!".to_string()),
+// cmp_values orders two values like Excel's comparison operators: numbers sort
+// before text, which sorts before booleans, text ignoring case. Empty stands for
+// 0, "" or FALSE depending on the other value.
+pub fn cmp_values(left: &Value, right: &Value) -> Ordering {
+    fn empty_as(other: &Value) -> Value {
+        match other {
+            Value::String(_) => Value::String(String::new()),
+            Value::Boolean(_) => Value::Boolean(false),
+            _ => Value::Number(0f64),
+        }
+    }
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Number(_) | Value::Date(_) => 0,
+            Value::String(_) => 1,
+            Value::Boolean(_) => 2,
+            _ => 3,
+        }
+    }
+    let (left, right) = match (left, right) {
+        (Value::Empty, Value::Empty) => return Ordering::Equal,
+        (Value::Empty, r) => (empty_as(r), r.clone()),
+        (l, Value::Empty) => (l.clone(), empty_as(l)),
+        (l, r) => (l.clone(), r.clone()),
+    };
+    match (&left, &right) {
+        (Value::Number(l) | Value::Date(l), Value::Number(r) | Value::Date(r)) => {
+            l.partial_cmp(r).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(l), Value::String(r)) => l.to_lowercase().cmp(&r.to_lowercase()),
+        (Value::Boolean(l), Value::Boolean(r)) => l.cmp(r),
+        _ => rank(&left).cmp(&rank(&right)),
+    }
+}
+
+// condition evaluates e as the condition of a logical function.
+fn condition(e: &Expression, evaluator: &Evaluator) -> Result<bool, Value> {
+    evaluator.evaluate(e).to_logical().map_err(Value::Error)
+}
+
+// logical_values return the logicals of an argument of AND, OR or XOR, the
+// text and empty items of arrays being ignored.
+fn logical_values(v: &Value) -> Result<Vec<bool>, ErrorValue> {
+    match v {
+        Value::Array(a) => a
+            .items
+            .iter()
+            .filter(|v| !matches!(v, Value::String(_) | Value::Empty))
+            .map(|v| v.to_logical())
+            .collect(),
+        v => v.to_logical().map(|b| vec![b]),
+    }
+}
+
+// IF(logical_test, value_if_true, [value_if_false])
+pub fn if_(args: &[Expression], evaluator: &Evaluator) -> Value {
+    match condition(&args[0], evaluator) {
+        Ok(true) => args
+            .get(1)
+            .map_or(Value::Boolean(true), |e| evaluator.evaluate(e)),
+        Ok(false) => args
+            .get(2)
+            .map_or(Value::Boolean(false), |e| evaluator.evaluate(e)),
+        Err(e) => e,
+    }
+}
+
+// IFS(logical_test1, value_if_true1, [logical_test2, value_if_true2], ...)
+pub fn ifs(args: &[Expression], evaluator: &Evaluator) -> Value {
+    if args.len() % 2 != 0 {
+        return Value::Error(ErrorKind::NA.into());
+    }
+    for pair in args.chunks(2) {
+        match condition(&pair[0], evaluator) {
+            Ok(true) => return evaluator.evaluate(&pair[1]),
+            Ok(false) => {}
+            Err(e) => return e,
+        }
+    }
+    Value::Error(ErrorKind::NA.into())
+}
+
+// AND(logical1, [logical2], ...) stops at the first FALSE argument.
+pub fn and(args: &[Expression], evaluator: &Evaluator) -> Value {
+    for arg in args {
+        match logical_values(&evaluator.evaluate(arg)) {
+            Ok(values) if values.contains(&false) => return Value::Boolean(false),
+            Ok(_) => {}
+            Err(e) => return Value::Error(e),
+        }
+    }
+    Value::Boolean(true)
+}
+
+// OR(logical1, [logical2], ...) stops at the first TRUE argument.
+pub fn or(args: &[Expression], evaluator: &Evaluator) -> Value {
+    for arg in args {
+        match logical_values(&evaluator.evaluate(arg)) {
+            Ok(values) if values.contains(&true) => return Value::Boolean(true),
+            Ok(_) => {}
+            Err(e) => return Value::Error(e),
+        }
+    }
+    Value::Boolean(false)
+}
+
+// NOT(logical)
+pub fn not(args: &[Value]) -> Value {
+    match args[0].to_logical() {
+        Ok(b) => Value::Boolean(!b),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// XOR(logical1, [logical2], ...) is TRUE when an odd number of arguments are.
+pub fn xor(args: &[Value]) -> Value {
+    let mut result = false;
+    for v in args {
+        match logical_values(v) {
+            Ok(values) => values.iter().for_each(|b| result ^= b),
+            Err(e) => return Value::Error(e),
+        }
+    }
+    Value::Boolean(result)
+}
+
+pub fn true_(_args: &[Value]) -> Value {
+    Value::Boolean(true)
+}
+
+pub fn false_(_args: &[Value]) -> Value {
+    Value::Boolean(false)
+}
+
+// IFERROR(value, value_if_error)
+pub fn iferror(args: &[Expression], evaluator: &Evaluator) -> Value {
+    let v = evaluator.evaluate(&args[0]);
+    if v.is_error() {
+        return evaluator.evaluate(&args[1]);
+    }
+    v
+}
+
+// CHOOSE(index_num, value1, [value2], ...)
+pub fn choose(args: &[Expression], evaluator: &Evaluator) -> Value {
+    let i = match evaluator.evaluate(&args[0]).coerce_number() {
+        Ok(n) => n.trunc(),
+        Err(e) => return Value::Error(e),
+    };
+    if i < 1.0 || i >= args.len() as f64 {
+        return Value::Error(ErrorKind::Value.into());
+    }
+    evaluator.evaluate(&args[i as usize])
+}
+
+// SWITCH(expression, value1, result1, [value2, result2], ..., [default])
+pub fn switch(args: &[Expression], evaluator: &Evaluator) -> Value {
+    let v = evaluator.evaluate(&args[0]);
+    if v.is_error() {
+        return v;
+    }
+    let mut cases = args[1..].chunks_exact(2);
+    for case in cases.by_ref() {
+        let candidate = evaluator.evaluate(&case[0]);
+        if candidate.is_error() {
+            return candidate;
+        }
+        if cmp_values(&v, &candidate) == Ordering::Equal {
+            return evaluator.evaluate(&case[1]);
+        }
+    }
+    match cases.remainder() {
+        [default] => evaluator.evaluate(default),
+        _ => Value::Error(ErrorKind::NA.into()),
+    }
+}
+
+// INDEX(array, row_num, [column_num])
+// a 0 row or column selects the whole column or row. The row_num of a single row
+// array is its column number.
+pub fn index(args: &[Value]) -> Value {
+    let array = as_array(&args[0]);
+    let mut positions = vec![];
+    for arg in &args[1..] {
+        match arg.coerce_number() {
+            Ok(n) if n >= 0.0 => positions.push(n.trunc() as usize),
+            Ok(_) => return Value::Error(ErrorKind::Value.into()),
+            Err(e) => return Value::Error(e),
+        }
+    }
+    let (row, col) = match positions[..] {
+        [col] if array.rows == 1 => (1, col),
+        [row] => (row, 0),
+        [row, col] => (row, col),
+        _ => unreachable!("INDEX takes 2 or 3 arguments"),
+    };
+    if row > array.rows || col > array.cols {
+        return Value::Error(ErrorKind::Ref.into());
+    }
+    let pick = |rows: Vec<usize>, cols: Vec<usize>| {
+        let items = rows
+            .iter()
+            .flat_map(|r| cols.iter().map(move |c| (*r, *c)))
+            .map(|(r, c)| array.get(r, c).cloned().unwrap_or(Value::Empty))
+            .collect();
+        Array::new(rows.len(), cols.len(), items)
+    };
+    match (row, col) {
+        (0, 0) => Value::Array(array.clone()),
+        (0, col) => Value::Array(pick((0..array.rows).collect(), vec![col - 1])),
+        (row, 0) if array.cols > 1 => Value::Array(pick(vec![row - 1], (0..array.cols).collect())),
+        (row, 0) => array.get(row - 1, 0).cloned().unwrap_or(Value::Empty),
+        (row, col) => array.get(row - 1, col - 1).cloned().unwrap_or(Value::Empty),
+    }
+}
+
+// as_array return v as an array, a scalar being a 1x1 one.
+fn as_array(v: &Value) -> Array {
+    match v {
+        Value::Array(a) => a.clone(),
+        v => Array::row(vec![v.clone()]),
+    }
+}
+
+// optional_number return the numeric argument i, or default when it is omitted.
+fn optional_number(args: &[Value], i: usize, default: f64) -> Result<f64, ErrorValue> {
+    match args.get(i) {
+        None | Some(Value::Empty) => Ok(default),
+        Some(v) => v.coerce_number(),
+    }
+}
+
+// optional_logical return the logical argument i, or false when it is omitted.
+fn optional_logical(args: &[Value], i: usize) -> Result<bool, ErrorValue> {
+    match args.get(i) {
+        None | Some(Value::Empty) => Ok(false),
+        Some(v) => v.to_logical(),
+    }
+}
+
+// sort_order reads a sort order argument, 1 for ascending and -1 for descending.
+fn sort_order(args: &[Value], i: usize) -> Result<bool, ErrorValue> {
+    let order = optional_number(args, i, 1.0)?;
+    if order == 1.0 || order == -1.0 {
+        Ok(order < 0.0)
+    } else {
+        Err(ErrorKind::Value.into())
+    }
+}
+
+// FILTER(array, include, [if_empty])
+// include is a column as high as array to filter its rows, or a row as wide as
+// array to filter its columns.
+pub fn filter(args: &[Value]) -> Value {
+    let array = as_array(&args[0]);
+    let include = as_array(&args[1]);
+    let by_row = include.cols == 1 && include.rows == array.rows;
+    let by_col = include.rows == 1 && include.cols == array.cols;
+    if !by_row && !by_col {
+        return Value::Error(ErrorKind::Value.into());
+    }
+    let array = if by_row { array } else { array.transpose() };
+    let mut rows = vec![];
+    for (row, keep) in array.iter_rows().zip(include.items.iter()) {
+        match keep.to_logical() {
+            Ok(true) => rows.push(row.to_vec()),
+            Ok(false) => {}
+            Err(e) => return Value::Error(e),
+        }
+    }
+    if rows.is_empty() {
+        return match args.get(2) {
+            Some(v) => v.clone(),
+            None => Value::Error(ErrorValue::with_message(
+                ErrorKind::Calc,
+                "FILTER matched nothing",
+            )),
         };
+    }
+    let result = Array::from_rows(rows);
+    Value::Array(if by_row { result } else { result.transpose() })
+}
+
+// SORT(array, [sort_index], [sort_order], [by_col])
+pub fn sort(args: &[Value]) -> Value {
+    let sorted = || -> Result<Array, ErrorValue> {
+        let index = optional_number(args, 1, 1.0)?.trunc();
+        let descending = sort_order(args, 2)?;
+        let by_col = optional_logical(args, 3)?;
+        let array = as_array(&args[0]);
+        let array = if by_col { array.transpose() } else { array };
+        if index < 1.0 || index > array.cols as f64 {
+            return Err(ErrorKind::Value.into());
+        }
+        let i = index as usize - 1;
+        let mut rows: Vec<&[Value]> = array.iter_rows().collect();
+        rows.sort_by(|a, b| {
+            let o = cmp_values(&a[i], &b[i]);
+            if descending {
+                o.reverse()
+            } else {
+                o
+            }
+        });
+        let result = Array::from_rows(rows.into_iter().map(|r| r.to_vec()).collect());
+        Ok(if by_col { result.transpose() } else { result })
+    };
+    match sorted() {
+        Ok(a) => Value::Array(a),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// SORTBY(array, by_array1, [sort_order1], [by_array2, sort_order2], ...)
+// the by arrays are columns as high as array to sort its rows, or rows as wide
+// as array to sort its columns.
+pub fn sortby(args: &[Value]) -> Value {
+    let sorted = || -> Result<Array, ErrorValue> {
+        let array = as_array(&args[0]);
+        let mut keys = vec![];
+        let mut by_col = None;
+        for (i, by) in args.iter().enumerate().skip(1).step_by(2) {
+            let by = as_array(by);
+            let col = if by.cols == 1 && by.rows == array.rows {
+                false
+            } else if by.rows == 1 && by.cols == array.cols {
+                true
+            } else {
+                return Err(ErrorKind::Value.into());
+            };
+            if by_col.is_some_and(|b| b != col) {
+                return Err(ErrorKind::Value.into());
+            }
+            by_col = Some(col);
+            keys.push((by.items, sort_order(args, i + 1)?));
+        }
+        let by_col = by_col.unwrap_or_default();
+        let array = if by_col { array.transpose() } else { array };
+        let rows: Vec<&[Value]> = array.iter_rows().collect();
+        let mut order: Vec<usize> = (0..rows.len()).collect();
+        order.sort_by(|&a, &b| {
+            keys.iter()
+                .map(|(key, descending)| {
+                    let o = cmp_values(&key[a], &key[b]);
+                    if *descending {
+                        o.reverse()
+                    } else {
+                        o
+                    }
+                })
+                .find(|o| *o != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+        let result = Array::from_rows(order.into_iter().map(|i| rows[i].to_vec()).collect());
+        Ok(if by_col { result.transpose() } else { result })
+    };
+    match sorted() {
+        Ok(a) => Value::Array(a),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// UNIQUE(array, [by_col], [exactly_once])
+pub fn unique(args: &[Value]) -> Value {
+    let (by_col, exactly_once) = match (optional_logical(args, 1), optional_logical(args, 2)) {
+        (Ok(by_col), Ok(exactly_once)) => (by_col, exactly_once),
+        (Err(e), _) | (_, Err(e)) => return Value::Error(e),
+    };
+    let array = as_array(&args[0]);
+    let array = if by_col { array.transpose() } else { array };
+    let same = |a: &[Value], b: &[Value]| {
+        a.iter()
+            .zip(b.iter())
+            .all(|(a, b)| cmp_values(a, b) == Ordering::Equal)
+    };
+    let mut distinct: Vec<(&[Value], usize)> = vec![];
+    for row in array.iter_rows() {
+        match distinct.iter_mut().find(|(r, _)| same(r, row)) {
+            Some((_, count)) => *count += 1,
+            None => distinct.push((row, 1)),
+        }
+    }
+    let rows: Vec<Vec<Value>> = distinct
+        .into_iter()
+        .filter(|(_, count)| !exactly_once || *count == 1)
+        .map(|(row, _)| row.to_vec())
+        .collect();
+    if rows.is_empty() {
+        return Value::Error(ErrorKind::Calc.into());
+    }
+    let result = Array::from_rows(rows);
+    Value::Array(if by_col { result.transpose() } else { result })
+}
+
+// shape reads the rows and columns arguments i and i + 1 of an array building
+// function, which must fit in a sheet and in MAX_ARRAY_CELLS.
+fn shape(args: &[Value], i: usize) -> Result<(usize, usize), ErrorValue> {
+    let rows = optional_number(args, i, 1.0)?.trunc();
+    let cols = optional_number(args, i + 1, 1.0)?.trunc();
+    if rows < 0.0 || cols < 0.0 || rows > MAX_ROW as f64 || cols > MAX_COLUMN as f64 {
+        return Err(ErrorKind::Value.into());
+    }
+    if rows == 0.0 || cols == 0.0 {
+        return Err(ErrorKind::Calc.into());
+    }
+    if rows * cols > MAX_ARRAY_CELLS as f64 {
+        return Err(ErrorValue::with_message(
+            ErrorKind::Num,
+            format!("array of {} cells is too large", rows * cols),
+        ));
+    }
+    Ok((rows as usize, cols as usize))
+}
+
+// SEQUENCE(rows, [columns], [start], [step])
+pub fn sequence(args: &[Value]) -> Value {
+    let build = || -> Result<Array, ErrorValue> {
+        let (rows, cols) = shape(args, 0)?;
+        let start = optional_number(args, 2, 1.0)?;
+        let step = optional_number(args, 3, 1.0)?;
+        let items = (0..rows * cols)
+            .map(|i| number(start + step * i as f64))
+            .collect();
+        Ok(Array::new(rows, cols, items))
+    };
+    match build() {
+        Ok(a) => Value::Array(a),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// RANDARRAY([rows], [columns], [min], [max], [integer])
+pub fn randarray(args: &[Value]) -> Value {
+    let build = || -> Result<Array, ErrorValue> {
+        let (rows, cols) = shape(args, 0)?;
+        let min = optional_number(args, 2, 0.0)?;
+        let max = optional_number(args, 3, 1.0)?;
+        let integer = optional_logical(args, 4)?;
+        if min > max || (integer && (min.fract() != 0.0 || max.fract() != 0.0)) {
+            return Err(ErrorKind::Value.into());
+        }
+        let items = (0..rows * cols)
+            .map(|_| {
+                if integer {
+                    Value::Number((min + random() * (max - min + 1.0)).floor())
+                } else {
+                    Value::Number(min + random() * (max - min))
+                }
+            })
+            .collect();
+        Ok(Array::new(rows, cols, items))
+    };
+    match build() {
+        Ok(a) => Value::Array(a),
+        Err(e) => Value::Error(e),
+    }
+}
+
+// random return a pseudo-random number in [0, 1) from a xorshift generator,
+// seeded once per thread.
+fn random() -> f64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
     })
 }
+
+// return values in eval context  key:value pairs, key should be like "11-byte-table-guid.11-byte-column-guid"
+pub fn get_ref_value(args: &[Value], context: &HashMap<String, Value>) -> Value {
+    if args.is_empty() {
+        return Value::Error(ErrorKind::Ref.into());
+    }
+    let ref_string = args[0].as_string();
+    let column_id = match ref_string.get(GUID_LENGTH + 1..) {
+        Some(column_id) => column_id,
+        None => {
+            return Value::Error(ErrorValue::with_message(
+                ErrorKind::Ref,
+                format!("malformed reference {}", ref_string),
+            ))
+        }
+    };
+    match context.get(column_id) {
+        Some(arg) => arg.clone(),
+        _ => Value::Error(ErrorValue::with_message(
+            ErrorKind::Ref,
+            format!("unknown column {}", column_id),
+        )),
+    }
+}
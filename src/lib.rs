@@ -1,27 +1,45 @@
+pub mod error;
 pub mod eval;
 pub mod exp;
 pub mod functions;
 pub mod oadate;
 pub mod parser;
+pub mod reference;
+pub mod registry;
 pub mod token;
 pub mod value;
 
+use error::ParseError;
 use eval::Evaluator;
 use parser::Parser;
+use registry::FunctionRegistry;
 use std::collections::HashMap;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[no_mangle]
-#[wasm_bindgen]
-pub extern "C" fn resolve(formula: &str) -> String {
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn resolve(formula: &str) -> Result<String, ParseError> {
     let mut p = Parser::new(formula);
     p.tokenize();
-    let e = p.parse();
+    let e = p.parse()?;
+    let functions = FunctionRegistry::builtin();
     // TODO: context should be initialized from input
     let context: HashMap<String, value::Value> = HashMap::default();
     let evaluator = Evaluator {
         expr: e,
         context: &context,
+        functions: &functions,
+        resolver: None,
     };
-    evaluator.resolve().as_string()
+    Ok(evaluator.resolve().as_string())
+}
+
+// validate checks that every function called by formula is a builtin function
+// given an acceptable number of arguments.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn validate(formula: &str) -> Result<(), ParseError> {
+    let mut p = Parser::new(formula);
+    p.tokenize();
+    let e = p.parse()?;
+    p.validate(&e, &FunctionRegistry::builtin())
 }
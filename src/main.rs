@@ -1,11 +1,5 @@
-mod eval;
-mod exp;
-mod functions;
-mod oadate;
-mod parser;
-mod token;
-mod value;
-
+use rexp::registry::FunctionRegistry;
+use rexp::{eval, parser, value};
 use std::collections::HashMap;
 use std::env;
 fn main() {
@@ -16,11 +10,19 @@ fn main() {
     }
     let mut p = parser::Parser::new(&args[1]);
     p.tokenize();
-    let e = p.parse();
+    let e = match p.parse() {
+        Ok(e) => e,
+        Err(err) => {
+            println!("Parse Error:\n {} \n", err);
+            return;
+        }
+    };
     let context: HashMap<String, value::Value> = HashMap::default();
     let evaluator = eval::Evaluator {
         expr: e,
         context: &context,
+        functions: &FunctionRegistry::builtin(),
+        resolver: None,
     };
     let result = evaluator.resolve();
     println!("Input Formula:\n {} \n", p.render());
@@ -1,7 +1,8 @@
+use std::convert::TryFrom;
 use time::{
     format_description::{parse, FormatItem},
     macros::{date, time},
-    Duration, OffsetDateTime, PrimitiveDateTime, Time,
+    Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time,
 };
 
 // converting between OLE Automation date aka OADate  vs NaiveDateTime. we have ignored timezone differential
@@ -21,6 +22,35 @@ pub fn to_oadate(t: PrimitiveDateTime) -> f64 {
     (t - dt).as_seconds_f64() / 8.64e4
 }
 
+// parse_date reads a date like "2021/02/10" or "2021-02-10", optionally followed
+// by a "hh:mm[:ss]" time, as an OADate.
+pub fn parse_date(s: &str) -> Option<f64> {
+    let mut parts = s.trim().splitn(2, ' ');
+    let date_part = parts.next()?;
+    let sep = if date_part.contains('/') { '/' } else { '-' };
+    let ymd: Vec<&str> = date_part.split(sep).collect();
+    if ymd.len() != 3 {
+        return None;
+    }
+    let month = Month::try_from(ymd[1].parse::<u8>().ok()?).ok()?;
+    let d = Date::from_calendar_date(ymd[0].parse().ok()?, month, ymd[2].parse().ok()?).ok()?;
+    let t = match parts.next() {
+        None => Time::MIDNIGHT,
+        Some(time_part) => {
+            let hms: Vec<&str> = time_part.trim().split(':').collect();
+            if hms.len() < 2 || hms.len() > 3 {
+                return None;
+            }
+            let second = match hms.get(2) {
+                Some(s) => s.parse().ok()?,
+                None => 0,
+            };
+            Time::from_hms(hms[0].parse().ok()?, hms[1].parse().ok()?, second).ok()?
+        }
+    };
+    Some(to_oadate(PrimitiveDateTime::new(d, t)))
+}
+
 pub fn today() -> f64 {
     let d = OffsetDateTime::now_utc();
     to_oadate(PrimitiveDateTime::new(d.date(), d.time()))
@@ -38,4 +68,12 @@ mod tests {
 
         assert_eq!(dt.format(&format).unwrap(), "2021-02-10 02:23:59");
     }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2021/02/10"), Some(44237.0));
+        assert_eq!(parse_date("2021-02-10 12:00"), Some(44237.5));
+        assert_eq!(parse_date("2021/02/30"), None);
+        assert_eq!(parse_date("abc"), None);
+    }
 }
@@ -1,30 +1,23 @@
 /// translated from github.com/xuri/efp , mostly
+use crate::error::{ErrorKind, ParseError, ParseErrorKind};
 use crate::exp;
-use crate::token::{Token, TokenSubType, TokenType, Tokens};
-use crate::value::Value;
-
-const QUOTE_DOUBLE: u8 = b'"';
-const QUOTE_SINGLE: u8 = b'\'';
-const BRACKET_CLOSE: u8 = b']';
-const BRACKET_OPEN: u8 = b'[';
-const BRACE_OPEN: u8 = b'{';
-const BRACE_CLOSE: u8 = b'}';
-const PAREN_OPEN: u8 = b'(';
-const PAREN_CLOSE: u8 = b')';
-const SEMICOLON: u8 = b';';
-const WHITESPACE: u8 = b' ';
-const COMMA: u8 = b',';
-const ERROR_START: u8 = b'#';
-
-const ERRORS: &[&str; 7] = &[
-    ",#NULL!,",
-    ",#DIV/0!,",
-    ",#VALUE!,",
-    ",#REF!,",
-    ",#NAME?,",
-    ",#NUM!,",
-    ",#N/A,",
-];
+use crate::reference::{CellRef, RangeRef, ReferenceStyle, SheetRef, TableRef};
+use crate::registry::FunctionRegistry;
+use crate::token::{Span, Token, TokenSubType, TokenType, Tokens};
+use crate::value::{Array, Value};
+
+const QUOTE_DOUBLE: char = '"';
+const QUOTE_SINGLE: char = '\'';
+const BRACKET_CLOSE: char = ']';
+const BRACKET_OPEN: char = '[';
+const BRACE_OPEN: char = '{';
+const BRACE_CLOSE: char = '}';
+const PAREN_OPEN: char = '(';
+const PAREN_CLOSE: char = ')';
+const SEMICOLON: char = ';';
+const WHITESPACE: char = ' ';
+const COMMA: char = ',';
+const ERROR_START: char = '#';
 
 const COMPARATORS: &[&str; 4] = &[",==,", ",>=,", ",<=,", ",<>,"];
 
@@ -32,43 +25,139 @@ const OPERATORS: &[&str; 9] = &["+", "-", "*", "/", "^", "&", "=", ">", "<"];
 
 const C_OPERATORS: &[&str; 3] = &["=", ">", "<"];
 
-#[allow(dead_code)]
+// Locale is the punctuation of formulas, which follows the regional settings
+// of the user: =SUM(1.5,2.5) in en-US is =SUMME(1,5;2,5) in de-DE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    // between function arguments, and the union operator
+    pub list_separator: char,
+    // between the integer and fractional parts of numbers
+    pub decimal_separator: char,
+    // between the items of a row of an array constant
+    pub array_column_separator: char,
+    // between the rows of an array constant
+    pub array_row_separator: char,
+}
+
+impl Locale {
+    // =SUM(1.5,2.5), {1,2;3,4}
+    pub const EN_US: Locale = Locale {
+        list_separator: COMMA,
+        decimal_separator: '.',
+        array_column_separator: COMMA,
+        array_row_separator: SEMICOLON,
+    };
+    // =SUM(1,5;2,5), {1.2;3.4}
+    pub const DE_DE: Locale = Locale {
+        list_separator: SEMICOLON,
+        decimal_separator: COMMA,
+        array_column_separator: '.',
+        array_row_separator: SEMICOLON,
+    };
+
+    // parse_number reads a number made of digits, the decimal separator of the
+    // locale and an exponent, like 1,5E-3 in German. Words such as inf or nan
+    // are not numbers.
+    fn parse_number(&self, s: &str) -> Option<f64> {
+        let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        let (mantissa, exponent) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let mut parts = mantissa.splitn(2, self.decimal_separator);
+        let (int, fraction) = (parts.next()?, parts.next().unwrap_or(""));
+        if int.len() + fraction.len() == 0 || !digits(int) || !digits(fraction) {
+            return None;
+        }
+        if let Some(exponent) = exponent {
+            let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            if exponent.is_empty() || !digits(exponent) {
+                return None;
+            }
+        }
+        s.replace(self.decimal_separator, ".").parse::<f64>().ok()
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Locale {
+        Locale::EN_US
+    }
+}
+
+// ParserOptions configures the notation of the formulas a Parser reads.
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    pub reference_style: ReferenceStyle,
+    pub locale: Locale,
+}
+
 pub struct Parser {
     formula: String,
+    options: ParserOptions,
     tokens: Tokens,
     token_stack: Tokens,
     offset: usize,
     in_string: bool,
     in_path: bool,
     in_range: bool,
+    // nesting of brackets in a structured reference like T[[#Headers],[A]]
+    bracket_depth: usize,
     in_error: bool,
+    error: Option<ParseError>,
 }
 
 impl Parser {
     pub fn new(formula: &str) -> Parser {
-        return Parser {
-            formula: formula.trim().to_string(),
+        Parser::with_options(formula, ParserOptions::default())
+    }
+
+    pub fn with_options(formula: &str, options: ParserOptions) -> Parser {
+        Parser {
+            formula: formula.to_string(),
+            options,
             tokens: Tokens::new(),
             token_stack: Tokens::new(),
             offset: 0,
             in_string: false,
             in_path: false,
             in_range: false,
+            bracket_depth: 0,
             in_error: false,
-        };
+            error: None,
+        }
     }
     // doubleChar provides function to get two characters after the current
     // position.
     pub fn double_char(&self) -> &str {
-        if self.formula.len() >= (self.offset + 2) {
-            return &self.formula[self.offset..self.offset + 2];
-        }
-        ""
+        let rest = &self.formula[self.offset..];
+        let end = rest.char_indices().nth(2).map_or(rest.len(), |(i, _)| i);
+        &rest[..end]
     }
 
-    // currentChar provides function to get the character of the current position.
-    pub fn current_char(&self) -> u8 {
-        *self.formula.as_bytes().get(self.offset).unwrap()
+    // currentChar provides function to get the character of the current position,
+    // or '\0' once the end of the formula is reached.
+    pub fn current_char(&self) -> char {
+        self.formula[self.offset..]
+            .chars()
+            .next()
+            .unwrap_or_default()
+    }
+
+    // nextChar provides function to get the character after the current
+    // position.
+    pub fn next_char(&self) -> char {
+        self.formula[self.offset..]
+            .chars()
+            .nth(1)
+            .unwrap_or_default()
+    }
+
+    // push_char moves the current character to token.
+    fn push_char(&mut self, token: &mut String) {
+        let c = self.current_char();
+        token.push(c);
+        self.offset += c.len_utf8();
     }
 
     // EOF provides function to check whether or not end of tokens stack.
@@ -76,182 +165,523 @@ impl Parser {
         self.offset >= self.formula.len()
     }
 
-    // Tokenize provides function to parse formula as a token stream (list).
+    // Tokenize provides function to parse formula as a token stream (list). A
+    // malformed formula is reported by the following call to parse.
     pub fn tokenize(&mut self) {
-        self.get_tokens();
+        if let Err(e) = self.get_tokens() {
+            self.error = Some(e);
+        }
     }
 
-    pub fn parse(&mut self) -> exp::Expression {
+    pub fn parse(&mut self) -> Result<exp::Expression, ParseError> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
+        }
         self.tokens.reset();
-        self.tokens.move_next(); //move to first one
-        let left = self.parse_primary();
-        self.build_expression_tree(0, left).unwrap()
+        self.tokens.advance(); //move to first one
+        if self.tokens.peek().is_none() {
+            return Ok(exp::Expression::Literal {
+                value: Value::Empty,
+                span: Span::new(0, self.formula.len()),
+            });
+        }
+        let e = self.parse_expression()?;
+        match self.tokens.peek() {
+            Some(t) => Err(self.error_at(ParseErrorKind::UnexpectedToken, t.span)),
+            None => Ok(e),
+        }
+    }
+
+    // validate checks that every function called by e is known to functions and
+    // given an acceptable number of arguments.
+    pub fn validate(
+        &self,
+        e: &exp::Expression,
+        functions: &FunctionRegistry,
+    ) -> Result<(), ParseError> {
+        match e {
+            exp::Expression::Literal { .. }
+            | exp::Expression::Reference { .. }
+            | exp::Expression::Range { .. }
+            | exp::Expression::SpillRange { .. }
+            | exp::Expression::Table { .. } => Ok(()),
+            exp::Expression::Unary { operand, .. } => self.validate(operand, functions),
+            exp::Expression::Binary { left, right, .. } => {
+                self.validate(left, functions)?;
+                self.validate(right, functions)
+            }
+            exp::Expression::Function {
+                function,
+                arguments,
+                span,
+            } => {
+                match functions.get(function) {
+                    None => return Err(self.error_at(ParseErrorKind::UnknownFunction, *span)),
+                    Some(f) if !f.accepts(arguments.len()) => {
+                        return Err(self.error_at(ParseErrorKind::ArgumentCount, *span))
+                    }
+                    _ => {}
+                }
+                arguments
+                    .iter()
+                    .try_for_each(|arg| self.validate(arg, functions))
+            }
+        }
+    }
+
+    fn error_at(&self, kind: ParseErrorKind, span: Span) -> ParseError {
+        ParseError::new(kind, span, &self.formula)
+    }
+
+    // new_reference return the reference, range or structured reference node
+    // for an operand, if it is one.
+    fn new_reference(&self, t: &Token) -> Option<exp::Expression> {
+        if let Some((sheet, cells)) = SheetRef::split(&t.value) {
+            let (reference, range) = match self.options.reference_style {
+                ReferenceStyle::A1 => (CellRef::parse(cells), RangeRef::parse(cells)),
+                ReferenceStyle::R1C1 => (CellRef::parse_r1c1(cells), RangeRef::parse_r1c1(cells)),
+            };
+            if let Some(reference) = reference {
+                return Some(exp::Expression::Reference {
+                    sheet,
+                    reference,
+                    style: self.options.reference_style,
+                    span: t.span,
+                });
+            }
+            if let Some(range) = range {
+                return Some(exp::Expression::Range {
+                    sheet,
+                    range,
+                    style: self.options.reference_style,
+                    span: t.span,
+                });
+            }
+        }
+        TableRef::parse(&t.value).map(|reference| exp::Expression::Table {
+            reference,
+            span: t.span,
+        })
     }
 
     fn new_value(t: &Token) -> Value {
         match t.sub_type {
             TokenSubType::Number => match t.value.parse::<f64>() {
                 Ok(f) => Value::Number(f),
-                Err(_) => Value::Error("#N/A".to_string()),
+                Err(_) => Value::Error(ErrorKind::NA.into()),
             },
-            TokenSubType::Logical => match t.value.parse::<bool>() {
+            TokenSubType::Logical => match t.value.to_lowercase().parse::<bool>() {
                 Ok(f) => Value::Boolean(f),
-                Err(_) => Value::Error("#N/A".to_string()),
+                Err(_) => Value::Error(ErrorKind::NA.into()),
             },
             TokenSubType::Text => Value::String(t.value.clone()),
-            TokenSubType::Error => Value::Error(t.value.clone()),
+            TokenSubType::Error => match t.value.parse::<ErrorKind>() {
+                Ok(kind) => Value::Error(kind.into()),
+                Err(_) => Value::Error(ErrorKind::NA.into()),
+            },
             _ => Value::Unknown,
         }
     }
 
-    fn parse_expression(&mut self) -> exp::Expression {
-        let left = self.parse_primary();
-        self.build_expression_tree(0, left).unwrap()
+    fn parse_expression(&mut self) -> Result<exp::Expression, ParseError> {
+        let left = self.parse_primary()?;
+        self.build_expression_tree(0, left)
     }
 
     fn build_expression_tree(
         &mut self,
         p: i8,
-        mut left: Option<exp::Expression>,
-    ) -> Option<exp::Expression> {
+        mut left: exp::Expression,
+    ) -> Result<exp::Expression, ParseError> {
         loop {
-            let p_token = exp::get_precedence(&self.tokens.current().borrow().value);
+            let t = match self.tokens.peek() {
+                Some(t) if t.token_type == TokenType::OperatorInfix => t.clone(),
+                _ => return Ok(left),
+            };
+            let op = match exp::BinaryOp::parse(&t.value) {
+                Some(op) => op,
+                None => return Err(self.error_at(ParseErrorKind::UnsupportedOperator, t.span)),
+            };
+            let p_token = op.precedence();
             if p_token < p {
-                // not a operator
-                return left;
+                return Ok(left);
             }
-            let bin_op = &self.tokens.current().into_inner().value;
-            if self.tokens.eof() {
-                return left;
+            self.tokens.advance();
+            if self.tokens.peek().is_none() {
+                return Err(self.error_at(ParseErrorKind::DanglingOperator, t.span));
             }
-            self.tokens.move_next();
-            let mut right = self.parse_primary();
-            right.as_ref()?; // return none is right is none
-            let next = exp::get_precedence(&self.tokens.current().borrow().value);
+            let mut right = self.parse_primary()?;
+            let next = match self.tokens.peek() {
+                Some(t) if t.token_type == TokenType::OperatorInfix => {
+                    exp::get_precedence(&t.value)
+                }
+                _ => -1,
+            };
             if p_token < next {
-                right = self.build_expression_tree(p_token + 1, right);
-                right.as_ref()?;
+                right = self.build_expression_tree(p_token + 1, right)?;
             }
-            left = Some(exp::Expression::Operator {
-                op: Some(bin_op.clone()),
-                left: Box::new(left.unwrap()),
+            let span = Span::new(left.span().start, right.span().end);
+            left = exp::Expression::Binary {
+                op,
+                left: Box::new(left),
                 right: Box::new(right),
-            })
+                span,
+            }
         }
     }
-    fn parse_function_expression(&mut self) -> exp::Expression {
-        let tokens = &mut self.tokens;
-        let t = tokens.current().into_inner();
-        tokens.move_next();
-        let mut exps: Vec<exp::Expression> = vec![];
-        if self.tokens.current().borrow().sub_type == TokenSubType::Stop {
-            // function with no args
-            self.tokens.move_next();
-            return exp::Expression::Function {
-                function: t.value,
-                arguments: exps,
-            };
+
+    // parse_argument parses one function argument, an omitted one being empty.
+    fn parse_argument(&mut self) -> Result<exp::Expression, ParseError> {
+        match self.tokens.peek() {
+            Some(t)
+                if t.token_type == TokenType::Argument
+                    || (t.token_type == TokenType::Function
+                        && t.sub_type == TokenSubType::Stop) =>
+            {
+                Ok(exp::Expression::Literal {
+                    value: Value::Empty,
+                    span: Span::new(t.span.start, t.span.start),
+                })
+            }
+            _ => self.parse_expression(),
         }
-        exps.push(self.parse_expression());
-        while self.tokens.current().borrow().sub_type != TokenSubType::Stop && !self.tokens.eof() {
-            self.tokens.move_next();
-            if self.tokens.current().borrow().token_type == TokenType::Argument {
-                continue;
+    }
+
+    fn parse_function_expression(&mut self) -> Result<exp::Expression, ParseError> {
+        let t = self.tokens.current().into_inner();
+        self.tokens.advance();
+        let mut exps: Vec<exp::Expression> = vec![];
+        if let Some(next) = self.tokens.peek() {
+            if next.sub_type == TokenSubType::Stop {
+                // function with no args
+                let span = Span::new(t.span.start, next.span.end);
+                self.tokens.advance();
+                return Ok(exp::Expression::Function {
+                    function: t.value,
+                    arguments: exps,
+                    span,
+                });
             }
-            exps.push(self.parse_expression());
         }
-        self.tokens.move_next();
-        exp::Expression::Function {
+        let end = loop {
+            exps.push(self.parse_argument()?);
+            match self.tokens.peek() {
+                Some(next) if next.token_type == TokenType::Argument => self.tokens.advance(),
+                Some(next)
+                    if next.token_type == TokenType::Function
+                        && next.sub_type == TokenSubType::Stop =>
+                {
+                    let end = next.span.end;
+                    self.tokens.advance();
+                    break end;
+                }
+                Some(next) => return Err(self.error_at(ParseErrorKind::UnexpectedToken, next.span)),
+                None => return Err(self.error_at(ParseErrorKind::UnbalancedParen, t.span)),
+            }
+        };
+        Ok(exp::Expression::Function {
             function: t.value,
             arguments: exps,
+            span: Span::new(t.span.start, end),
+        })
+    }
+
+    // array_constant turns the ARRAY and ARRAYROW pseudo-functions the tokenizer
+    // makes of braces into an array literal. Items must be constants, and rows
+    // of the same length.
+    fn array_constant(&self, e: exp::Expression) -> Result<exp::Expression, ParseError> {
+        let (rows, span) = match e {
+            exp::Expression::Function {
+                arguments, span, ..
+            } => (arguments, span),
+            e => return Err(self.error_at(ParseErrorKind::UnexpectedToken, e.span())),
+        };
+        let mut items = vec![];
+        let mut cols = None;
+        for row in rows.iter() {
+            let row_items = match row {
+                exp::Expression::Function {
+                    function,
+                    arguments,
+                    ..
+                } if function == "ARRAYROW" && !arguments.is_empty() => arguments,
+                row => return Err(self.error_at(ParseErrorKind::UnexpectedToken, row.span())),
+            };
+            if cols.is_some_and(|cols| cols != row_items.len()) {
+                return Err(self.error_at(ParseErrorKind::RaggedArray, row.span()));
+            }
+            cols = Some(row_items.len());
+            for item in row_items {
+                items.push(self.array_item(item)?);
+            }
         }
+        let cols = cols.unwrap_or_default();
+        Ok(exp::Expression::Literal {
+            value: Value::Array(Array::new(rows.len(), cols, items)),
+            span,
+        })
     }
-    fn parse_primary(&mut self) -> Option<exp::Expression> {
-        let t = self.tokens.current().into_inner();
-        match t.token_type {
-            TokenType::Function => Some(self.parse_function_expression()),
-            TokenType::Operand => {
-                let e = exp::Expression::Literal(Parser::new_value(&t));
-                self.tokens.move_next();
-                Some(e)
-            }
-            TokenType::Subexpression => {
-                self.tokens.move_next(); // skip paren
-                let e = self.parse_expression();
-                if self.tokens.current().borrow().token_type != TokenType::Subexpression {
-                    return Some(exp::Expression::Literal(Value::Error("#N/A".to_string())));
-                }
-                self.tokens.move_next(); // skip paren
-                Some(e)
-            }
-            TokenType::OperatorPrefix | TokenType::OperatorInfix => {
-                self.tokens.move_next();
-                if self.tokens.eof() {
-                    return None;
-                }
-                Some(exp::Expression::Operator {
-                    op: Some("-".to_string()),
-                    left: Box::new(exp::Expression::Literal(Value::Empty)),
-                    right: Box::new(self.parse_primary()),
+
+    // array_item return the value of an array constant item: a number, possibly
+    // signed, a string, a boolean or an error.
+    fn array_item(&self, e: &exp::Expression) -> Result<Value, ParseError> {
+        match e {
+            exp::Expression::Literal { value, span } => match value {
+                Value::Empty | Value::Array(_) => {
+                    Err(self.error_at(ParseErrorKind::UnexpectedToken, *span))
+                }
+                v => Ok(v.clone()),
+            },
+            exp::Expression::Unary { op, operand, span } => match (op, &**operand) {
+                (
+                    exp::UnaryOp::Negate,
+                    exp::Expression::Literal {
+                        value: Value::Number(n),
+                        ..
+                    },
+                ) => Ok(Value::Number(-n)),
+                (
+                    exp::UnaryOp::Plus,
+                    exp::Expression::Literal {
+                        value: Value::Number(n),
+                        ..
+                    },
+                ) => Ok(Value::Number(*n)),
+                _ => Err(self.error_at(ParseErrorKind::UnexpectedToken, *span)),
+            },
+            e => Err(self.error_at(ParseErrorKind::UnexpectedToken, e.span())),
+        }
+    }
+
+    // parse_primary parses an operand with its prefix and postfix operators, the
+    // former binding tighter.
+    fn parse_primary(&mut self) -> Result<exp::Expression, ParseError> {
+        let mut e = self.parse_prefix()?;
+        while let Some(t) = self.tokens.peek() {
+            if t.token_type != TokenType::OperatorPostfix {
+                break;
+            }
+            let span = Span::new(e.span().start, t.span.end);
+            let spill = t.value == "#";
+            self.tokens.advance();
+            e = match e {
+                exp::Expression::Reference {
+                    sheet,
+                    reference,
+                    style,
+                    ..
+                } if spill => exp::Expression::SpillRange {
+                    sheet,
+                    anchor: reference,
+                    style,
+                    span,
+                },
+                _ if spill => {
+                    return Err(self.error_at(
+                        ParseErrorKind::UnexpectedToken,
+                        Span::new(span.end - 1, span.end),
+                    ))
+                }
+                e => exp::Expression::Unary {
+                    op: exp::UnaryOp::Percent,
+                    operand: Box::new(e),
+                    span,
+                },
+            };
+        }
+        Ok(e)
+    }
+
+    fn parse_prefix(&mut self) -> Result<exp::Expression, ParseError> {
+        let t = match self.tokens.peek() {
+            Some(t) => t.clone(),
+            None => {
+                let end = self.formula.len();
+                return Err(self.error_at(ParseErrorKind::DanglingOperator, Span::new(end, end)));
+            }
+        };
+        match (t.token_type, t.sub_type) {
+            (TokenType::Function, TokenSubType::Start)
+                if t.value == "ARRAY" && self.is_array_token(&t) =>
+            {
+                let e = self.parse_function_expression()?;
+                self.array_constant(e)
+            }
+            (TokenType::Function, TokenSubType::Start) => self.parse_function_expression(),
+            (TokenType::Operand, TokenSubType::Range) => {
+                self.tokens.advance();
+                match self.new_reference(&t) {
+                    Some(e) => Ok(e),
+                    None => Err(self.error_at(ParseErrorKind::UnexpectedToken, t.span)),
+                }
+            }
+            (TokenType::Operand, _) => {
+                let e = exp::Expression::Literal {
+                    value: Parser::new_value(&t),
+                    span: t.span,
+                };
+                self.tokens.advance();
+                Ok(e)
+            }
+            (TokenType::Subexpression, TokenSubType::Start) => {
+                self.tokens.advance(); // skip paren
+                let e = self.parse_expression()?;
+                match self.tokens.peek() {
+                    Some(close) if close.token_type == TokenType::Subexpression => {}
+                    Some(close) => {
+                        return Err(self.error_at(ParseErrorKind::UnexpectedToken, close.span))
+                    }
+                    None => return Err(self.error_at(ParseErrorKind::UnbalancedParen, t.span)),
+                }
+                self.tokens.advance(); // skip paren
+                Ok(e)
+            }
+            (TokenType::OperatorPrefix, _) => {
+                self.tokens.advance();
+                if self.tokens.peek().is_none() {
+                    return Err(self.error_at(ParseErrorKind::DanglingOperator, t.span));
+                }
+                let operand = self.parse_prefix()?;
+                Ok(exp::Expression::Unary {
+                    op: if t.value == "+" {
+                        exp::UnaryOp::Plus
+                    } else {
+                        exp::UnaryOp::Negate
+                    },
+                    span: Span::new(t.span.start, operand.span().end),
+                    operand: Box::new(operand),
                 })
             }
-            _ => Some(exp::Expression::Literal(Value::Error("#N/A".to_string()))),
+            _ => Err(self.error_at(ParseErrorKind::UnexpectedToken, t.span)),
+        }
+    }
+
+    // token_span return the span of an accumulated token which ends at the current
+    // position.
+    fn token_span(&self, token: &str) -> Span {
+        Span::new(self.offset - token.len(), self.offset)
+    }
+
+    // add_token moves an accumulated token to the token list.
+    fn add_token(&mut self, token: &mut String, token_type: TokenType, sub_type: TokenSubType) {
+        let span = self.token_span(token);
+        self.tokens
+            .add(std::mem::take(token), token_type, sub_type, span);
+    }
+
+    // pop_stack moves the stop token matching the innermost open paren or brace to
+    // the token list.
+    fn pop_stack(&mut self) -> Result<(), ParseError> {
+        let span = Span::new(self.offset, self.offset + 1);
+        match self.token_stack.pop() {
+            Some(mut t) => {
+                t.span = span;
+                self.tokens.add_ref(&t);
+                Ok(())
+            }
+            None => Err(self.error_at(ParseErrorKind::UnbalancedParen, span)),
         }
     }
 
     // getTokens return a token stream (list).
-    pub fn get_tokens(&mut self) {
-        if !self.formula.is_empty() && self.formula.as_bytes().get(0).unwrap() != &b'=' {
-            self.formula.insert(0, '=');
+    pub fn get_tokens(&mut self) -> Result<(), ParseError> {
+        // start over from a previous call
+        self.tokens = Tokens::new();
+        self.token_stack = Tokens::new();
+        self.in_string = false;
+        self.in_path = false;
+        self.in_range = false;
+        self.bracket_depth = 0;
+        self.in_error = false;
+        self.error = None;
+        // skip leading white-space and the optional "="
+        self.offset = self.formula.len() - self.formula.trim_start().len();
+        if self.current_char() == '=' {
+            self.offset += 1;
         }
         let mut token = String::new();
+        let mut string_start = 0;
         // state-dependent character evaluation (order is important)
         while !self.eof() {
             // double-quoted strings
             // embeds are doubled
             // end marks token
             if self.in_string {
+                if self.current_char() == QUOTE_DOUBLE && self.next_char() == QUOTE_DOUBLE {
+                    token.push('"');
+                    self.offset += 2;
+                    continue;
+                }
                 if self.current_char() == QUOTE_DOUBLE {
                     self.in_string = false;
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Text);
+                    self.tokens.add(
+                        token,
+                        TokenType::Operand,
+                        TokenSubType::Text,
+                        Span::new(string_start, self.offset + 1),
+                    );
                     token = String::new();
+                    self.offset += 1;
                 } else {
-                    token += std::str::from_utf8(&[self.current_char()]).unwrap();
+                    self.push_char(&mut token);
                 }
-                self.offset += 1;
                 continue;
             }
-            if self.in_error {
-                if self.current_char() == BRACKET_CLOSE {
-                    self.in_range = false
+
+            // single-quoted strings (sheet names, possibly with a workbook)
+            // embeds are doubled
+            // end does not mark a token
+            if self.in_path {
+                if self.current_char() == QUOTE_SINGLE {
+                    if self.next_char() == QUOTE_SINGLE {
+                        token.push('\'');
+                        self.offset += 1;
+                    } else {
+                        self.in_path = false;
+                    }
                 }
-                token += std::str::from_utf8(&[self.current_char()]).unwrap();
-                self.offset += 1;
+                self.push_char(&mut token);
+                continue;
+            }
+
+            // bracketed strings (R1C1 range index, linked workbook name or table
+            // columns and items)
+            // nested brackets and "'" escapes of structured references are kept
+            // end does not mark a token
+            if self.in_range {
+                match self.current_char() {
+                    BRACKET_OPEN => self.bracket_depth += 1,
+                    BRACKET_CLOSE => {
+                        self.bracket_depth -= 1;
+                        self.in_range = self.bracket_depth > 0;
+                    }
+                    QUOTE_SINGLE if self.next_char() != '\0' => {
+                        token.push('\'');
+                        self.offset += 1;
+                    }
+                    _ => {}
+                }
+                self.push_char(&mut token);
                 continue;
             }
 
+            // error values
+            // end marks a token, determined from absolute list of values
             if self.in_error {
-                token += std::str::from_utf8(&[self.current_char()]).unwrap();
-                self.offset += 1;
-                if ERRORS
-                    .contains(&std::str::from_utf8(&[b',', self.current_char(), b',']).unwrap())
-                {
+                self.push_char(&mut token);
+                if token.parse::<ErrorKind>().is_ok() {
                     self.in_error = false;
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Error);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Error);
                 }
                 continue;
             }
             if self.current_char() == QUOTE_DOUBLE {
                 if !token.is_empty() {
                     // not expected
-                    self.tokens
-                        .add(token, TokenType::Unknown, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Unknown, TokenSubType::Nothing);
                 }
                 self.in_string = true;
+                string_start = self.offset;
                 self.offset += 1;
                 continue;
             }
@@ -259,32 +689,38 @@ impl Parser {
             if self.current_char() == QUOTE_SINGLE {
                 if !token.is_empty() {
                     // not expected
-                    self.tokens
-                        .add(token, TokenType::Unknown, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Unknown, TokenSubType::Nothing);
                 }
-                //self.InPath = true
+                self.in_path = true;
+                string_start = self.offset;
+                token.push('\'');
                 self.offset += 1;
                 continue;
             }
 
             if self.current_char() == BRACKET_OPEN {
                 self.in_range = true;
-                token += std::str::from_utf8(&[self.current_char()]).unwrap();
+                self.bracket_depth = 1;
+                self.push_char(&mut token);
+                continue;
+            }
+
+            // "#" after an operand is the spill range operator, as in A1#
+            if self.current_char() == ERROR_START && !token.is_empty() {
+                self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
+                self.tokens.add(
+                    "#".to_string(),
+                    TokenType::OperatorPostfix,
+                    TokenSubType::Nothing,
+                    Span::new(self.offset, self.offset + 1),
+                );
                 self.offset += 1;
                 continue;
             }
 
             if self.current_char() == ERROR_START {
-                if !token.is_empty() {
-                    // not expected
-                    self.tokens
-                        .add(token, TokenType::Unknown, TokenSubType::Nothing);
-                    token = String::new();
-                }
                 self.in_error = true;
-                token += std::str::from_utf8(&[self.current_char()]).unwrap();
-                self.offset += 1;
+                self.push_char(&mut token);
                 continue;
             }
 
@@ -292,16 +728,16 @@ impl Parser {
             if self.current_char() == BRACE_OPEN {
                 if !token.is_empty() {
                     // not expected
-                    self.tokens
-                        .add(token, TokenType::Unknown, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Unknown, TokenSubType::Nothing);
                 }
+                let span = Span::new(self.offset, self.offset + 1);
                 self.token_stack.push(
                     self.tokens
                         .add(
                             "ARRAY".to_string(),
                             TokenType::Function,
                             TokenSubType::Start,
+                            span,
                         )
                         .clone(),
                 );
@@ -311,6 +747,7 @@ impl Parser {
                             "ARRAYROW".to_string(),
                             TokenType::Function,
                             TokenSubType::Start,
+                            span,
                         )
                         .clone(),
                 );
@@ -318,27 +755,45 @@ impl Parser {
                 continue;
             }
 
-            if self.current_char() == SEMICOLON {
+            // the row separator doubles as the list separator outside arrays in
+            // some locales
+            let separator = self.current_char();
+            let in_array = self.in_array_row();
+            let locale = &self.options.locale;
+            if separator == locale.array_row_separator
+                && (in_array || separator != locale.list_separator)
+            {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
-                self.tokens.add_ref(&self.token_stack.pop().unwrap());
-                self.tokens
-                    .add(",".to_string(), TokenType::Argument, TokenSubType::Nothing);
-                self.offset += 1;
+                self.pop_stack()?;
+                let span = Span::new(self.offset, self.offset + separator.len_utf8());
+                self.tokens.add(
+                    ";".to_string(),
+                    TokenType::Argument,
+                    TokenSubType::Nothing,
+                    span,
+                );
+                self.token_stack.push(
+                    self.tokens
+                        .add(
+                            "ARRAYROW".to_string(),
+                            TokenType::Function,
+                            TokenSubType::Start,
+                            span,
+                        )
+                        .clone(),
+                );
+                self.offset += separator.len_utf8();
                 continue;
             }
 
             if self.current_char() == BRACE_CLOSE {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
-                self.tokens.add_ref(&self.token_stack.pop().unwrap());
-                self.tokens.add_ref(&self.token_stack.pop().unwrap());
+                self.pop_stack()?;
+                self.pop_stack()?;
                 self.offset += 1;
                 continue;
             }
@@ -346,69 +801,62 @@ impl Parser {
             // trim white-space
             if self.current_char() == WHITESPACE {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
-                self.tokens
-                    .add(String::new(), TokenType::Whitespace, TokenSubType::Nothing);
+                let start = self.offset;
                 self.offset += 1;
-                while (self.current_char() == WHITESPACE) && (!self.eof()) {
+                while !self.eof() && self.current_char() == WHITESPACE {
                     self.offset += 1;
                 }
+                self.tokens.add(
+                    String::new(),
+                    TokenType::Whitespace,
+                    TokenSubType::Nothing,
+                    Span::new(start, self.offset),
+                );
                 continue;
             }
 
             // multi-character comparators
-            let mut double_char: Vec<u8> = self.double_char().bytes().collect();
-            double_char.insert(0, b',');
-            double_char.push(b',');
-            if COMPARATORS.contains(&std::str::from_utf8(&double_char).unwrap()) {
+            if COMPARATORS.contains(&format!(",{},", self.double_char()).as_str()) {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
                 self.tokens.add(
                     self.double_char().to_string(),
                     TokenType::OperatorInfix,
                     TokenSubType::Logical,
+                    Span::new(self.offset, self.offset + 2),
                 );
                 self.offset += 2;
                 continue;
             }
 
             // standard infix operators
-            if OPERATORS.contains(&std::str::from_utf8(&[self.current_char()]).unwrap()) {
+            if OPERATORS.contains(&self.current_char().to_string().as_str()) {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
                 self.tokens.add(
-                    std::str::from_utf8(&[self.current_char()])
-                        .unwrap()
-                        .to_string(),
+                    self.current_char().to_string(),
                     TokenType::OperatorInfix,
                     TokenSubType::Nothing,
+                    Span::new(self.offset, self.offset + 1),
                 );
                 self.offset += 1;
                 continue;
             }
 
             // standard postfix operators
-            if self.current_char() == b'%' {
+            if self.current_char() == '%' {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
                 self.tokens.add(
-                    std::str::from_utf8(&[self.current_char()])
-                        .unwrap()
-                        .to_string(),
+                    self.current_char().to_string(),
                     TokenType::OperatorPostfix,
                     TokenSubType::Nothing,
+                    Span::new(self.offset, self.offset + 1),
                 );
                 self.offset += 1;
                 continue;
@@ -417,12 +865,14 @@ impl Parser {
             // start subexpression or function
             if self.current_char() == PAREN_OPEN {
                 if !token.is_empty() {
+                    let span = Span::new(self.offset - token.len(), self.offset + 1);
                     self.token_stack.push(
                         self.tokens
                             .add(
-                                token.to_string().to_uppercase(),
+                                token.to_uppercase(),
                                 TokenType::Function,
                                 TokenSubType::Start,
+                                span,
                             )
                             .clone(),
                     );
@@ -430,7 +880,12 @@ impl Parser {
                 } else {
                     self.token_stack.push(
                         self.tokens
-                            .add(String::new(), TokenType::Subexpression, TokenSubType::Start)
+                            .add(
+                                String::new(),
+                                TokenType::Subexpression,
+                                TokenSubType::Start,
+                                Span::new(self.offset, self.offset + 1),
+                            )
                             .clone(),
                     );
                 }
@@ -439,53 +894,71 @@ impl Parser {
             }
 
             // function, subexpression, array parameters
-            if self.current_char() == COMMA {
+            let separator = if in_array {
+                self.options.locale.array_column_separator
+            } else {
+                self.options.locale.list_separator
+            };
+            if self.current_char() == separator {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
-                if *self.token_stack.tp() != TokenType::Function {
-                    self.tokens.add(
-                        std::str::from_utf8(&[self.current_char()])
-                            .unwrap()
-                            .to_string(),
-                        TokenType::OperatorInfix,
-                        TokenSubType::Union,
-                    );
+                let in_function = matches!(
+                    self.token_stack.top(),
+                    Some(t) if t.token_type == TokenType::Function
+                );
+                let (token_type, sub_type) = if in_function {
+                    (TokenType::Argument, TokenSubType::Nothing)
                 } else {
-                    self.tokens.add(
-                        std::str::from_utf8(&[self.current_char()])
-                            .unwrap()
-                            .to_string(),
-                        TokenType::Argument,
-                        TokenSubType::Nothing,
-                    );
-                }
-                self.offset += 1;
+                    (TokenType::OperatorInfix, TokenSubType::Union)
+                };
+                self.tokens.add(
+                    ",".to_string(),
+                    token_type,
+                    sub_type,
+                    Span::new(self.offset, self.offset + separator.len_utf8()),
+                );
+                self.offset += separator.len_utf8();
                 continue;
             }
 
             // stop subexpression
             if self.current_char() == PAREN_CLOSE {
                 if !token.is_empty() {
-                    self.tokens
-                        .add(token, TokenType::Operand, TokenSubType::Nothing);
-                    token = String::new();
+                    self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
                 }
-                self.tokens.add_ref(&self.token_stack.pop().unwrap());
+                self.pop_stack()?;
                 self.offset += 1;
                 continue;
             }
 
             // token accumulation
-            token += std::str::from_utf8(&[self.current_char()]).unwrap();
-            self.offset += 1;
+            self.push_char(&mut token);
+        }
+        if self.in_string {
+            return Err(self.error_at(
+                ParseErrorKind::UnterminatedString,
+                Span::new(string_start, self.offset),
+            ));
+        }
+        if self.in_path {
+            return Err(self.error_at(
+                ParseErrorKind::UnterminatedString,
+                Span::new(string_start, self.offset),
+            ));
+        }
+        if self.in_error {
+            return Err(self.error_at(ParseErrorKind::UnexpectedToken, self.token_span(&token)));
+        }
+        if self.in_range {
+            return Err(self.error_at(ParseErrorKind::UnbalancedParen, self.token_span(&token)));
         }
         // dump remaining accumulation
         if !token.is_empty() {
-            self.tokens
-                .add(token, TokenType::Operand, TokenSubType::Nothing);
+            self.add_token(&mut token, TokenType::Operand, TokenSubType::Nothing);
+        }
+        if let Some(t) = self.token_stack.top() {
+            return Err(self.error_at(ParseErrorKind::UnbalancedParen, t.span));
         }
 
         // move all tokens to a new collection, excluding all unnecessary white-space tokens
@@ -493,36 +966,31 @@ impl Parser {
         while self.tokens.move_next() {
             let token = self.tokens.current().into_inner();
             if token.token_type == TokenType::Whitespace {
-                if self.tokens.bof() || self.tokens.eof() {
-                } else if !(((self.tokens.previous().token_type == TokenType::Function)
-                    && (self.tokens.previous().sub_type == TokenSubType::Stop))
-                    || ((self.tokens.previous().token_type == TokenType::Subexpression)
-                        && (self.tokens.previous().sub_type == TokenSubType::Stop))
-                    || (self.tokens.previous().token_type == TokenType::Operand))
-                {
-                } else if !(((self.tokens.next().token_type == TokenType::Function)
-                    && (self.tokens.next().sub_type == TokenSubType::Start))
-                    || ((self.tokens.next().token_type == TokenType::Subexpression)
-                        && (self.tokens.next().sub_type == TokenSubType::Start))
-                    || (self.tokens.next().token_type == TokenType::Operand))
+                if !self.tokens.bof()
+                    && !self.tokens.eof()
+                    && ((self.tokens.previous().token_type == TokenType::Function
+                        && self.tokens.previous().sub_type == TokenSubType::Stop)
+                        || (self.tokens.previous().token_type == TokenType::Subexpression
+                            && self.tokens.previous().sub_type == TokenSubType::Stop)
+                        || self.tokens.previous().token_type == TokenType::Operand)
+                    && ((self.tokens.next().token_type == TokenType::Function
+                        && self.tokens.next().sub_type == TokenSubType::Start)
+                        || (self.tokens.next().token_type == TokenType::Subexpression
+                            && self.tokens.next().sub_type == TokenSubType::Start)
+                        || self.tokens.next().token_type == TokenType::Operand)
                 {
-                } else {
                     tokens2.add(
                         token.value.clone(),
                         TokenType::OperatorInfix,
                         TokenSubType::Intersection,
+                        token.span,
                     );
                 }
                 continue;
             }
 
-            tokens2.add_ref(&Token {
-                value: token.value.clone(),
-                token_type: token.token_type,
-                sub_type: token.sub_type,
-            })
+            tokens2.add_ref(&token)
         }
-
         while tokens2.move_next() {
             let token = tokens2.current().into_inner();
             if token.token_type == TokenType::OperatorInfix && token.value == "-" {
@@ -543,7 +1011,7 @@ impl Parser {
             }
             if token.token_type == TokenType::OperatorInfix && token.value == "+" {
                 if tokens2.bof() {
-                    tokens2.set_current_field(None, Some(TokenType::Noop), None)
+                    tokens2.set_current_field(None, Some(TokenType::OperatorPrefix), None)
                 } else if tokens2.previous().token_type == TokenType::Function
                     && tokens2.previous().sub_type == TokenSubType::Stop
                     || (tokens2.previous().token_type == TokenType::Subexpression
@@ -553,7 +1021,7 @@ impl Parser {
                 {
                     tokens2.set_current_field(None, None, Some(TokenSubType::Math))
                 } else {
-                    tokens2.set_current_field(None, Some(TokenType::Noop), None)
+                    tokens2.set_current_field(None, Some(TokenType::OperatorPrefix), None)
                 }
                 continue;
             }
@@ -561,7 +1029,7 @@ impl Parser {
             if token.token_type == TokenType::OperatorInfix
                 && token.sub_type == TokenSubType::Nothing
             {
-                if C_OPERATORS.contains(&&token.value[0..1]) {
+                if C_OPERATORS.iter().any(|op| token.value.starts_with(op)) {
                     tokens2.set_current_field(None, None, Some(TokenSubType::Logical))
                 } else if token.value == "&" {
                     tokens2.set_current_field(None, None, Some(TokenSubType::Concatenation))
@@ -572,13 +1040,27 @@ impl Parser {
             }
 
             if token.token_type == TokenType::Operand && token.sub_type == TokenSubType::Nothing {
-                match token.value.parse::<f64>() {
-                    Ok(_) => tokens2.set_current_field(None, None, Some(TokenSubType::Number)),
-                    Err(_) => {
+                // numbers are kept with a "." decimal separator
+                match self.options.locale.parse_number(&token.value) {
+                    Some(_) => tokens2.set_current_field(
+                        Some(
+                            token
+                                .value
+                                .replace(self.options.locale.decimal_separator, "."),
+                        ),
+                        None,
+                        Some(TokenSubType::Number),
+                    ),
+                    None => {
                         if token.value.to_uppercase() == "TRUE"
                             || token.value.to_uppercase() == "FALSE"
                         {
                             tokens2.set_current_field(None, None, Some(TokenSubType::Logical))
+                        } else if self.new_reference(&token).is_some() {
+                            tokens2.set_current_field(None, None, Some(TokenSubType::Range))
+                        } else if token.value.starts_with('\'') || token.value.contains('!') {
+                            // a sheet prefix not followed by a reference
+                            return Err(self.error_at(ParseErrorKind::UnexpectedToken, token.span));
                         } else {
                             tokens2.set_current_field(None, None, Some(TokenSubType::Text))
                         }
@@ -587,8 +1069,8 @@ impl Parser {
                 continue;
             }
             if token.token_type == TokenType::Function {
-                if !token.value.is_empty() && &token.value[0..1] == "@" {
-                    tokens2.set_current_field(Some(token.value[1..].to_string()), None, None)
+                if let Some(name) = token.value.strip_prefix('@') {
+                    tokens2.set_current_field(Some(name.to_string()), None, None)
                 }
                 continue;
             }
@@ -599,15 +1081,12 @@ impl Parser {
         while tokens2.move_next() {
             let t = tokens2.current().into_inner();
             if t.token_type != TokenType::Noop {
-                tokens.add_ref(&Token {
-                    value: t.value.clone(),
-                    token_type: t.token_type,
-                    sub_type: t.sub_type,
-                })
+                tokens.add_ref(&t)
             }
         }
         tokens.reset();
         self.tokens = tokens;
+        Ok(())
     }
 
     pub fn pretty(&self) -> String {
@@ -634,15 +1113,49 @@ impl Parser {
         output
     }
 
+    // is_array_token return whether t is one of the ARRAY and ARRAYROW
+    // pseudo-functions the tokenizer makes of array braces and row separators.
+    fn is_array_token(&self, t: &Token) -> bool {
+        (t.value == "ARRAY" || t.value == "ARRAYROW")
+            && self.formula[t.span.start..]
+                .starts_with([BRACE_OPEN, self.options.locale.array_row_separator])
+    }
+
+    // in_array_row return whether the tokenizer is between the items of an array
+    // constant row.
+    fn in_array_row(&self) -> bool {
+        self.token_stack
+            .top()
+            .is_some_and(|t| t.value == "ARRAYROW" && self.is_array_token(t))
+    }
+
     // Render provides function to get formatted formula after parsed.
     pub fn render(&self) -> String {
+        self.render_locale(&self.options.locale)
+    }
+
+    // render_locale return the parsed formula with the separators of locale,
+    // which translates it from the locale it was read in.
+    pub fn render_locale(&self, locale: &Locale) -> String {
         let mut output = String::from("");
+        // the functions started, with whether they are array pseudo-functions
+        let mut functions = vec![];
         for item in self.tokens.items.iter() {
             if item.token_type == TokenType::Function && item.sub_type == TokenSubType::Start {
-                output = format!("{}({}", output, item.value)
+                let array = self.is_array_token(item);
+                functions.push((item.value.as_str(), array));
+                match (item.value.as_str(), array) {
+                    ("ARRAY", true) => output += "{",
+                    (_, true) => {}
+                    (name, false) => output = format!("{}{}(", output, name),
+                }
             } else if item.token_type == TokenType::Function && item.sub_type == TokenSubType::Stop
             {
-                output += ")"
+                match functions.pop() {
+                    Some(("ARRAY", true)) => output += "}",
+                    Some((_, true)) => {}
+                    _ => output += ")",
+                }
             } else if item.token_type == TokenType::Subexpression
                 && item.sub_type == TokenSubType::Start
             {
@@ -651,12 +1164,27 @@ impl Parser {
                 && item.sub_type == TokenSubType::Stop
             {
                 output += ")"
-            } else if item.token_type == TokenType::Operand && item.sub_type == TokenSubType::Text {
-                output = format!("{}\"{}\"", output, item.value)
+            } else if item.token_type == TokenType::Operand
+                && item.sub_type == TokenSubType::Text
+                && self.formula[item.span.start..].starts_with('"')
+            {
+                output = format!("{}\"{}\"", output, item.value.replace('"', "\"\""))
             } else if item.token_type == TokenType::OperatorInfix
                 && item.sub_type == TokenSubType::Intersection
             {
                 output += " "
+            } else if item.token_type == TokenType::Argument || item.sub_type == TokenSubType::Union
+            {
+                output.push(match functions.last() {
+                    Some(("ARRAY", true)) => locale.array_row_separator,
+                    Some((_, true)) => locale.array_column_separator,
+                    _ => locale.list_separator,
+                })
+            } else if item.token_type == TokenType::Operand && item.sub_type == TokenSubType::Number
+            {
+                output += &item
+                    .value
+                    .replace('.', &locale.decimal_separator.to_string())
             } else {
                 output = format!("{}{}", output, item.value)
             }
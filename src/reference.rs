@@ -0,0 +1,886 @@
+use crate::error::ErrorKind;
+use crate::value::Value;
+use std::fmt;
+
+pub const MAX_ROW: u32 = 1_048_576;
+pub const MAX_COLUMN: u32 = 16_384;
+
+// ReferenceStyle is the notation of cell references in formulas.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ReferenceStyle {
+    // B2, $B$2
+    #[default]
+    A1,
+    // R2C2, R[-1]C[1]
+    R1C1,
+}
+
+// Coord is a row or column of a reference.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Coord {
+    // $A or $1 (R1C1: R1), the 1-based index, which stays put when the formula
+    // is copied
+    Absolute(u32),
+    // A or 1, the 1-based index, which moves with the formula
+    Relative(u32),
+    // R1C1 notation R[-1] or C, the distance from the cell of the formula
+    Offset(i32),
+}
+
+impl Coord {
+    // index return the 1-based index, or 0 for an Offset, which needs an anchor
+    // cell to be located.
+    pub fn index(&self) -> u32 {
+        match self {
+            Coord::Absolute(i) | Coord::Relative(i) => *i,
+            Coord::Offset(_) => 0,
+        }
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        matches!(self, Coord::Absolute(_))
+    }
+
+    pub fn is_offset(&self) -> bool {
+        matches!(self, Coord::Offset(_))
+    }
+
+    fn new(index: u32, absolute: bool) -> Coord {
+        if absolute {
+            Coord::Absolute(index)
+        } else {
+            Coord::Relative(index)
+        }
+    }
+
+    // to_offset return the R1C1 form of the coord, relative to anchor.
+    pub fn to_offset(self, anchor: u32) -> Coord {
+        match self {
+            Coord::Relative(i) => Coord::Offset(i as i32 - anchor as i32),
+            c => c,
+        }
+    }
+
+    // to_relative return the A1 form of the coord, relative to anchor, or None
+    // when it falls outside of the sheet.
+    pub fn to_relative(self, anchor: u32, max: u32) -> Option<Coord> {
+        match self {
+            Coord::Offset(d) => {
+                let i = anchor as i64 + d as i64;
+                if i < 1 || i > max as i64 {
+                    return None;
+                }
+                Some(Coord::Relative(i as u32))
+            }
+            c => Some(c),
+        }
+    }
+
+    fn fmt_column(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_absolute() {
+            f.write_str("$")?;
+        }
+        f.write_str(&column_name(self.index()))
+    }
+
+    fn fmt_row(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_absolute() {
+            f.write_str("$")?;
+        }
+        write!(f, "{}", self.index())
+    }
+
+    // fmt_r1c1 writes the coord in R1C1 notation, after axis "R" or "C".
+    fn fmt_r1c1(&self, f: &mut fmt::Formatter<'_>, axis: &str) -> fmt::Result {
+        match self {
+            Coord::Offset(0) => f.write_str(axis),
+            Coord::Offset(d) => write!(f, "{}[{}]", axis, d),
+            Coord::Absolute(i) | Coord::Relative(i) => write!(f, "{}{}", axis, i),
+        }
+    }
+}
+
+// CellRef is a reference to a single cell, like B2, $B$2 or R[-1]C2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CellRef {
+    pub row: Coord,
+    pub col: Coord,
+}
+
+impl CellRef {
+    // parse reads an A1 style cell reference.
+    pub fn parse(s: &str) -> Option<CellRef> {
+        let (col, rest) = parse_column(s)?;
+        let (row, rest) = parse_row(rest)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(CellRef { row, col })
+    }
+
+    // parse_r1c1 reads an R1C1 style cell reference.
+    pub fn parse_r1c1(s: &str) -> Option<CellRef> {
+        let (row, rest) = parse_r1c1_coord(s, 'R', MAX_ROW)?;
+        let (col, rest) = parse_r1c1_coord(rest, 'C', MAX_COLUMN)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(CellRef { row, col })
+    }
+
+    // is_anchored return whether the reference locates a cell by itself, that is
+    // it has no R1C1 offsets.
+    pub fn is_anchored(&self) -> bool {
+        !self.row.is_offset() && !self.col.is_offset()
+    }
+
+    fn fmt_r1c1(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.row.fmt_r1c1(f, "R")?;
+        self.col.fmt_r1c1(f, "C")
+    }
+
+    // display return the reference written in the given notation.
+    pub fn display(&self, style: ReferenceStyle) -> Styled<'_, CellRef> {
+        Styled {
+            reference: self,
+            style,
+        }
+    }
+
+    fn fmt_style(&self, f: &mut fmt::Formatter<'_>, style: ReferenceStyle) -> fmt::Result {
+        if style == ReferenceStyle::R1C1 || !self.is_anchored() {
+            return self.fmt_r1c1(f);
+        }
+        self.col.fmt_column(f)?;
+        self.row.fmt_row(f)
+    }
+
+    // to_r1c1 rewrites the relative coords of the reference into offsets from
+    // the anchor cell.
+    pub fn to_r1c1(&self, anchor: &CellRef) -> CellRef {
+        CellRef {
+            row: self.row.to_offset(anchor.row.index()),
+            col: self.col.to_offset(anchor.col.index()),
+        }
+    }
+
+    // to_a1 rewrites the offsets of the reference into relative coords from the
+    // anchor cell, or return None when the cell falls outside of the sheet.
+    pub fn to_a1(&self, anchor: &CellRef) -> Option<CellRef> {
+        Some(CellRef {
+            row: self.row.to_relative(anchor.row.index(), MAX_ROW)?,
+            col: self.col.to_relative(anchor.col.index(), MAX_COLUMN)?,
+        })
+    }
+}
+
+// Display writes the reference in R1C1 notation when it has offsets, and in A1
+// notation otherwise.
+impl fmt::Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_style(f, ReferenceStyle::A1)
+    }
+}
+
+// Styled is a reference displayed in a given notation. References with offsets
+// are written in R1C1 notation whatever the style.
+pub struct Styled<'a, T> {
+    reference: &'a T,
+    style: ReferenceStyle,
+}
+
+impl fmt::Display for Styled<'_, CellRef> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.reference.fmt_style(f, self.style)
+    }
+}
+
+impl fmt::Display for Styled<'_, RangeRef> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.reference.fmt_style(f, self.style)
+    }
+}
+
+// RangeRef is a reference to a rectangle of cells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RangeRef {
+    // B2:C10
+    Area(CellRef, CellRef),
+    // A:C, whole columns
+    Columns(Coord, Coord),
+    // 1:3, whole rows
+    Rows(Coord, Coord),
+}
+
+impl RangeRef {
+    // parse reads an A1 style range made of two cells, columns or rows joined by
+    // the ":" range operator.
+    pub fn parse(s: &str) -> Option<RangeRef> {
+        let mut parts = s.splitn(2, ':');
+        let (start, end) = (parts.next()?, parts.next()?);
+        if let (Some(start), Some(end)) = (CellRef::parse(start), CellRef::parse(end)) {
+            return Some(RangeRef::Area(start, end));
+        }
+        if let (Some((start, "")), Some((end, ""))) = (parse_column(start), parse_column(end)) {
+            return Some(RangeRef::Columns(start, end));
+        }
+        match (parse_row(start), parse_row(end)) {
+            (Some((start, "")), Some((end, ""))) => Some(RangeRef::Rows(start, end)),
+            _ => None,
+        }
+    }
+
+    // parse_r1c1 reads an R1C1 style range, like R1C1:R10C3, R[-1]:R[1] or C2.
+    // A single row or column stands for a range of its own.
+    pub fn parse_r1c1(s: &str) -> Option<RangeRef> {
+        let mut parts = s.splitn(2, ':');
+        let start = parts.next()?;
+        let end = parts.next().unwrap_or(start);
+        if let (Some(start), Some(end)) = (CellRef::parse_r1c1(start), CellRef::parse_r1c1(end)) {
+            if s.contains(':') {
+                return Some(RangeRef::Area(start, end));
+            }
+            return None;
+        }
+        let rows = (
+            parse_r1c1_coord(start, 'R', MAX_ROW),
+            parse_r1c1_coord(end, 'R', MAX_ROW),
+        );
+        if let (Some((start, "")), Some((end, ""))) = rows {
+            return Some(RangeRef::Rows(start, end));
+        }
+        let columns = (
+            parse_r1c1_coord(start, 'C', MAX_COLUMN),
+            parse_r1c1_coord(end, 'C', MAX_COLUMN),
+        );
+        match columns {
+            (Some((start, "")), Some((end, ""))) => Some(RangeRef::Columns(start, end)),
+            _ => None,
+        }
+    }
+
+    pub fn is_anchored(&self) -> bool {
+        match self {
+            RangeRef::Area(start, end) => start.is_anchored() && end.is_anchored(),
+            RangeRef::Columns(start, end) | RangeRef::Rows(start, end) => {
+                !start.is_offset() && !end.is_offset()
+            }
+        }
+    }
+
+    pub fn to_r1c1(&self, anchor: &CellRef) -> RangeRef {
+        match self {
+            RangeRef::Area(start, end) => {
+                RangeRef::Area(start.to_r1c1(anchor), end.to_r1c1(anchor))
+            }
+            RangeRef::Columns(start, end) => RangeRef::Columns(
+                start.to_offset(anchor.col.index()),
+                end.to_offset(anchor.col.index()),
+            ),
+            RangeRef::Rows(start, end) => RangeRef::Rows(
+                start.to_offset(anchor.row.index()),
+                end.to_offset(anchor.row.index()),
+            ),
+        }
+    }
+
+    pub fn to_a1(&self, anchor: &CellRef) -> Option<RangeRef> {
+        Some(match self {
+            RangeRef::Area(start, end) => RangeRef::Area(start.to_a1(anchor)?, end.to_a1(anchor)?),
+            RangeRef::Columns(start, end) => RangeRef::Columns(
+                start.to_relative(anchor.col.index(), MAX_COLUMN)?,
+                end.to_relative(anchor.col.index(), MAX_COLUMN)?,
+            ),
+            RangeRef::Rows(start, end) => RangeRef::Rows(
+                start.to_relative(anchor.row.index(), MAX_ROW)?,
+                end.to_relative(anchor.row.index(), MAX_ROW)?,
+            ),
+        })
+    }
+
+    // display return the range written in the given notation.
+    pub fn display(&self, style: ReferenceStyle) -> Styled<'_, RangeRef> {
+        Styled {
+            reference: self,
+            style,
+        }
+    }
+
+    fn fmt_style(&self, f: &mut fmt::Formatter<'_>, style: ReferenceStyle) -> fmt::Result {
+        let r1c1 = style == ReferenceStyle::R1C1 || !self.is_anchored();
+        match self {
+            RangeRef::Area(start, end) if r1c1 => {
+                start.fmt_r1c1(f)?;
+                f.write_str(":")?;
+                end.fmt_r1c1(f)
+            }
+            RangeRef::Area(start, end) => write!(f, "{}:{}", start, end),
+            RangeRef::Columns(start, end) if r1c1 => {
+                start.fmt_r1c1(f, "C")?;
+                f.write_str(":")?;
+                end.fmt_r1c1(f, "C")
+            }
+            RangeRef::Columns(start, end) => {
+                start.fmt_column(f)?;
+                f.write_str(":")?;
+                end.fmt_column(f)
+            }
+            RangeRef::Rows(start, end) if r1c1 => {
+                start.fmt_r1c1(f, "R")?;
+                f.write_str(":")?;
+                end.fmt_r1c1(f, "R")
+            }
+            RangeRef::Rows(start, end) => {
+                start.fmt_row(f)?;
+                f.write_str(":")?;
+                end.fmt_row(f)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RangeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_style(f, ReferenceStyle::A1)
+    }
+}
+
+// SheetRef is the sheet prefix of a reference, like Sheet1!, 'Q1 Sales'!,
+// Sheet1:Sheet3! or [Book.xlsx]Sheet1!.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetRef {
+    pub workbook: Option<String>,
+    pub first: String,
+    // the last sheet of a 3D reference spanning several sheets
+    pub last: Option<String>,
+}
+
+impl SheetRef {
+    // split separates the sheet prefix of a reference from the cells, with the
+    // quotes of a quoted sheet name removed. None means the prefix is malformed.
+    pub fn split(s: &str) -> Option<(Option<SheetRef>, &str)> {
+        let (prefix, rest) = match s.strip_prefix('\'') {
+            Some(quoted) => {
+                let mut name = String::new();
+                let mut chars = quoted.char_indices();
+                loop {
+                    match chars.next()? {
+                        (i, '\'') if quoted[i + 1..].starts_with('\'') => {
+                            name.push('\'');
+                            chars.next();
+                        }
+                        (i, '\'') => break (name, quoted[i + 1..].strip_prefix('!')?),
+                        (_, c) => name.push(c),
+                    }
+                }
+            }
+            None => match s.find('!') {
+                Some(i) => (s[..i].to_string(), &s[i + 1..]),
+                None => return Some((None, s)),
+            },
+        };
+        let (workbook, sheets) = match prefix.strip_prefix('[') {
+            Some(p) => {
+                let end = p.find(']')?;
+                (Some(p[..end].to_string()), &p[end + 1..])
+            }
+            None => (None, &prefix[..]),
+        };
+        let mut names = sheets.splitn(2, ':');
+        let first = names.next().filter(|n| !n.is_empty())?.to_string();
+        let last = match names.next() {
+            Some("") => return None,
+            n => n.map(|n| n.to_string()),
+        };
+        Some((
+            Some(SheetRef {
+                workbook,
+                first,
+                last,
+            }),
+            rest,
+        ))
+    }
+
+    // needs_quotes return whether the prefix must be written between single
+    // quotes to be read back.
+    fn needs_quotes(&self) -> bool {
+        let plain = |name: &str| {
+            !name.starts_with(|c: char| c.is_ascii_digit())
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+                && CellRef::parse(name).is_none()
+        };
+        !plain(&self.first)
+            || self.last.as_deref().is_some_and(|n| !plain(n))
+            || self
+                .workbook
+                .as_deref()
+                .is_some_and(|w| w.contains(['\'', ' ']))
+    }
+}
+
+impl fmt::Display for SheetRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut prefix = String::new();
+        if let Some(workbook) = &self.workbook {
+            prefix += &format!("[{}]", workbook);
+        }
+        prefix += &self.first;
+        if let Some(last) = &self.last {
+            prefix += &format!(":{}", last);
+        }
+        if self.needs_quotes() {
+            write!(f, "'{}'!", prefix.replace('\'', "''"))
+        } else {
+            write!(f, "{}!", prefix)
+        }
+    }
+}
+
+// TableItem is a special item specifier of a structured reference.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TableItem {
+    All,
+    Data,
+    Headers,
+    Totals,
+    ThisRow,
+}
+
+impl TableItem {
+    pub const ALL: [TableItem; 5] = [
+        TableItem::All,
+        TableItem::Data,
+        TableItem::Headers,
+        TableItem::Totals,
+        TableItem::ThisRow,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TableItem::All => "#All",
+            TableItem::Data => "#Data",
+            TableItem::Headers => "#Headers",
+            TableItem::Totals => "#Totals",
+            TableItem::ThisRow => "#This Row",
+        }
+    }
+}
+
+// TableRef is a structured reference to the columns of a table, like
+// Table1[Amount], [@Amount] or Table1[[#Headers],[First]:[Last]].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRef {
+    // None for the table containing the formula
+    pub table: Option<String>,
+    pub items: Vec<TableItem>,
+    // the first and last columns, which are the same for a single column; None
+    // means all columns
+    pub columns: Option<(String, String)>,
+}
+
+impl TableRef {
+    // parse reads a structured reference.
+    pub fn parse(s: &str) -> Option<TableRef> {
+        let open = s.find('[')?;
+        let inner = s[open..].strip_prefix('[')?.strip_suffix(']')?;
+        let table = match &s[..open] {
+            "" => None,
+            name if name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '.') =>
+            {
+                Some(name.to_string())
+            }
+            _ => return None,
+        };
+        let mut reference = TableRef {
+            table,
+            items: vec![],
+            columns: None,
+        };
+        if let Some(rest) = inner.strip_prefix('@') {
+            // [@Col] and [@[Col]] are short for [[#This Row],[Col]]
+            reference.items.push(TableItem::ThisRow);
+            if !rest.is_empty() {
+                let column = match rest.strip_prefix('[') {
+                    Some(r) => unescape_column(r.strip_suffix(']')?)?,
+                    None => unescape_column(rest)?,
+                };
+                reference.columns = Some((column.clone(), column));
+            }
+        } else if inner.starts_with('[') {
+            for part in split_table_items(inner)? {
+                reference.add(part.trim())?;
+            }
+        } else if !inner.is_empty() {
+            reference.add(&format!("[{}]", inner))?;
+        }
+        Some(reference)
+    }
+
+    // add appends an item, a column or a range of columns, which must come
+    // after the items.
+    fn add(&mut self, part: &str) -> Option<()> {
+        if self.columns.is_some() {
+            return None;
+        }
+        let part = part.strip_prefix('[')?.strip_suffix(']')?;
+        if part.starts_with('#') {
+            let item = TableItem::ALL
+                .iter()
+                .find(|i| i.as_str().eq_ignore_ascii_case(part))?;
+            self.items.push(*item);
+            return Some(());
+        }
+        let (first, last) = match split_once_unescaped(part, "]:[") {
+            Some((first, last)) => (unescape_column(first)?, unescape_column(last)?),
+            None => {
+                let column = unescape_column(part)?;
+                (column.clone(), column)
+            }
+        };
+        self.columns = Some((first, last));
+        Some(())
+    }
+}
+
+impl fmt::Display for TableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(table) = &self.table {
+            f.write_str(table)?;
+        }
+        let columns = self.columns.as_ref().map(|(first, last)| {
+            if first == last {
+                format!("[{}]", escape_column(first))
+            } else {
+                format!("[{}]:[{}]", escape_column(first), escape_column(last))
+            }
+        });
+        match (&self.items[..], &columns) {
+            ([], None) => f.write_str("[]"),
+            ([], Some(c)) if !c.contains("]:[") => f.write_str(c),
+            ([TableItem::ThisRow], Some(c)) if !c.contains("]:[") => {
+                let name = &c[1..c.len() - 1];
+                if name.contains(|c: char| !c.is_alphanumeric() && c != '_' && c != '.') {
+                    write!(f, "[@{}]", c)
+                } else {
+                    write!(f, "[@{}]", name)
+                }
+            }
+            ([item], None) => write!(f, "[{}]", item.as_str()),
+            (items, _) => {
+                let mut parts: Vec<String> =
+                    items.iter().map(|i| format!("[{}]", i.as_str())).collect();
+                parts.extend(columns);
+                write!(f, "[{}]", parts.join(","))
+            }
+        }
+    }
+}
+
+// split_table_items splits the bracketed parts of a structured reference on the
+// commas between them.
+fn split_table_items(s: &str) -> Option<Vec<&str>> {
+    let mut parts = vec![];
+    let (mut depth, mut start, mut escaped) = (0, 0, false);
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\'' => escaped = true,
+            '[' => depth += 1,
+            ']' if depth == 0 => return None,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    parts.push(&s[start..]);
+    Some(parts)
+}
+
+// split_once_unescaped splits s on the first pat not preceded by a "'" escape.
+fn split_once_unescaped<'s>(s: &'s str, pat: &str) -> Option<(&'s str, &'s str)> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\'' {
+            escaped = true;
+        } else if s[i..].starts_with(pat) {
+            return Some((&s[..i], &s[i + pat.len()..]));
+        }
+    }
+    None
+}
+
+// unescape_column removes the "'" escapes of the special characters of a
+// column name.
+fn unescape_column(s: &str) -> Option<String> {
+    let mut name = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => name.push(chars.next()?),
+            '[' | ']' | '#' => return None,
+            c => name.push(c),
+        }
+    }
+    if name.trim().is_empty() {
+        return None;
+    }
+    Some(name)
+}
+
+fn escape_column(s: &str) -> String {
+    let mut name = String::new();
+    for c in s.chars() {
+        if matches!(c, '\'' | '[' | ']' | '#') {
+            name.push('\'');
+        }
+        name.push(c);
+    }
+    name
+}
+
+// Resolver is implemented by hosts to supply the values of referenced cells.
+// Ranges are expected to resolve to arrays. `sheet` is None for references to
+// the sheet of the formula.
+pub trait Resolver {
+    fn cell(&self, sheet: Option<&SheetRef>, reference: &CellRef) -> Value;
+    fn range(&self, sheet: Option<&SheetRef>, range: &RangeRef) -> Value;
+    // spill return the array spilled from the anchor cell, for A1# references;
+    // see `Evaluator::resolve_spill`.
+    fn spill(&self, sheet: Option<&SheetRef>, anchor: &CellRef) -> Value {
+        let _ = (sheet, anchor);
+        Value::Error(ErrorKind::Ref.into())
+    }
+    // table return the cells of a structured reference, which hosts without
+    // tables do not need to resolve.
+    fn table(&self, reference: &TableRef) -> Value {
+        let _ = reference;
+        Value::Error(ErrorKind::Ref.into())
+    }
+}
+
+// column_name return the letters naming the 1-based column index.
+pub fn column_name(mut index: u32) -> String {
+    let mut name = Vec::new();
+    while index > 0 {
+        let rem = (index - 1) % 26;
+        name.insert(0, b'A' + rem as u8);
+        index = (index - 1) / 26;
+    }
+    String::from_utf8(name).unwrap_or_default()
+}
+
+// parse_column reads an optionally absolute column name from the start of s.
+fn parse_column(s: &str) -> Option<(Coord, &str)> {
+    let (absolute, s) = match s.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let len = s.bytes().take_while(|b| b.is_ascii_alphabetic()).count();
+    if len == 0 || len > 3 {
+        return None;
+    }
+    let index = s[..len].bytes().fold(0u32, |acc, b| {
+        acc * 26 + (b.to_ascii_uppercase() - b'A' + 1) as u32
+    });
+    if index > MAX_COLUMN {
+        return None;
+    }
+    Some((Coord::new(index, absolute), &s[len..]))
+}
+
+// parse_r1c1_coord reads the row or column part of an R1C1 reference from the
+// start of s: the axis letter followed by an index, an offset between brackets
+// or nothing for the row or column of the formula.
+fn parse_r1c1_coord(s: &str, axis: char, max: u32) -> Option<(Coord, &str)> {
+    let s = s
+        .strip_prefix(axis)
+        .or_else(|| s.strip_prefix(axis.to_ascii_lowercase()))?;
+    if let Some(rest) = s.strip_prefix('[') {
+        let end = rest.find(']')?;
+        let offset = rest[..end].parse::<i32>().ok()?;
+        if offset.unsigned_abs() >= max {
+            return None;
+        }
+        return Some((Coord::Offset(offset), &rest[end + 1..]));
+    }
+    let len = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 {
+        return Some((Coord::Offset(0), s));
+    }
+    match s[..len].parse::<u32>() {
+        Ok(index) if index >= 1 && index <= max => Some((Coord::Absolute(index), &s[len..])),
+        _ => None,
+    }
+}
+
+// parse_row reads an optionally absolute row number from the start of s.
+fn parse_row(s: &str) -> Option<(Coord, &str)> {
+    let (absolute, s) = match s.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let len = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 || s.starts_with('0') {
+        return None;
+    }
+    let index = s[..len].parse::<u32>().ok()?;
+    if index > MAX_ROW {
+        return None;
+    }
+    Some((Coord::new(index, absolute), &s[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_a1() {
+        let c = CellRef::parse("$b$12").unwrap();
+        assert_eq!(c.col, Coord::Absolute(2));
+        assert_eq!(c.row, Coord::Absolute(12));
+        assert_eq!(c.to_string(), "$B$12");
+        assert_eq!(
+            CellRef::parse("XFD1048576").unwrap().to_string(),
+            "XFD1048576"
+        );
+        assert_eq!(CellRef::parse("XFE1"), None);
+        assert_eq!(CellRef::parse("A0"), None);
+        assert_eq!(CellRef::parse("A1B"), None);
+        assert_eq!(RangeRef::parse("A1:$C10").unwrap().to_string(), "A1:$C10");
+        assert_eq!(
+            RangeRef::parse("a:$C"),
+            Some(RangeRef::Columns(Coord::Relative(1), Coord::Absolute(3)))
+        );
+        assert_eq!(
+            RangeRef::parse("1:1"),
+            Some(RangeRef::Rows(Coord::Relative(1), Coord::Relative(1)))
+        );
+        assert_eq!(RangeRef::parse("A1:B"), None);
+        assert_eq!(column_name(28), "AB");
+    }
+
+    #[test]
+    fn test_parse_r1c1() {
+        let c = CellRef::parse_r1c1("R[-1]C2").unwrap();
+        assert_eq!(c.row, Coord::Offset(-1));
+        assert_eq!(c.col, Coord::Absolute(2));
+        assert_eq!(c.to_string(), "R[-1]C2");
+        assert_eq!(CellRef::parse_r1c1("rc").unwrap().to_string(), "RC");
+        let c = CellRef::parse_r1c1("R1C1").unwrap();
+        assert_eq!(c.display(ReferenceStyle::R1C1).to_string(), "R1C1");
+        assert_eq!(c.display(ReferenceStyle::A1).to_string(), "$A$1");
+        assert_eq!(CellRef::parse_r1c1("R0C1"), None);
+        assert_eq!(CellRef::parse_r1c1("R[1]"), None);
+        assert_eq!(CellRef::parse_r1c1("RC[x]"), None);
+        assert_eq!(
+            RangeRef::parse_r1c1("R1C1:R[9]C3").unwrap().to_string(),
+            "R1C1:R[9]C3"
+        );
+        assert_eq!(
+            RangeRef::parse_r1c1("C[-1]"),
+            Some(RangeRef::Columns(Coord::Offset(-1), Coord::Offset(-1)))
+        );
+        assert_eq!(
+            RangeRef::parse_r1c1("R:R[2]").unwrap().to_string(),
+            "R:R[2]"
+        );
+        assert_eq!(RangeRef::parse_r1c1("RC"), None);
+        let r = RangeRef::parse_r1c1("R1:R2").unwrap();
+        assert_eq!(r.display(ReferenceStyle::R1C1).to_string(), "R1:R2");
+        assert_eq!(r.to_string(), "$1:$2");
+
+        let anchor = CellRef::parse("C3").unwrap();
+        let c = CellRef::parse("A$4").unwrap();
+        assert_eq!(c.to_r1c1(&anchor).to_string(), "R4C[-2]");
+        assert_eq!(c.to_r1c1(&anchor).to_a1(&anchor), Some(c));
+        let c = CellRef::parse_r1c1("R[-3]C").unwrap();
+        assert_eq!(c.to_a1(&anchor), None);
+        let r = RangeRef::parse("B:D").unwrap().to_r1c1(&anchor);
+        assert_eq!(r.to_string(), "C[-1]:C[1]");
+        assert_eq!(r.to_a1(&CellRef::parse("A1").unwrap()), None);
+    }
+
+    #[test]
+    fn test_split_sheet() {
+        let cases = [
+            ("A1", None, "A1"),
+            ("Sheet1!A1", Some("Sheet1!"), "A1"),
+            ("'Q1 Sales'!B2:B9", Some("'Q1 Sales'!"), "B2:B9"),
+            ("'Bob''s'!A1", Some("'Bob''s'!"), "A1"),
+            ("Sheet1:Sheet3!A1", Some("Sheet1:Sheet3!"), "A1"),
+            ("[Book.xlsx]Sheet1!A1", Some("[Book.xlsx]Sheet1!"), "A1"),
+            (
+                "'[My Book.xlsx]Sheet 1'!C:C",
+                Some("'[My Book.xlsx]Sheet 1'!"),
+                "C:C",
+            ),
+            ("'A1'!A1", Some("'A1'!"), "A1"),
+        ];
+        for (s, sheet, rest) in cases.iter() {
+            let (got, r) = SheetRef::split(s).unwrap();
+            assert_eq!(got.map(|g| g.to_string()).as_deref(), *sheet, "{}", s);
+            assert_eq!(r, *rest, "{}", s);
+        }
+        let (sheet, _) = SheetRef::split("'Bob''s'!A1").unwrap();
+        assert_eq!(sheet.unwrap().first, "Bob's");
+        assert_eq!(SheetRef::split("'open!A1"), None);
+        assert_eq!(SheetRef::split("!A1"), None);
+        assert_eq!(SheetRef::split("Sheet1:!A1"), None);
+    }
+
+    #[test]
+    fn test_parse_table() {
+        let cases = [
+            ("Table1[Amount]", "Table1[Amount]"),
+            ("Table1[[Amount]]", "Table1[Amount]"),
+            ("[@Amount]", "[@Amount]"),
+            ("Sales[@[Unit Price]]", "Sales[@[Unit Price]]"),
+            ("Sales[[#This Row],[Amount]]", "Sales[@Amount]"),
+            ("Sales[#all]", "Sales[#All]"),
+            ("Sales[]", "Sales[]"),
+            (
+                "Sales[[#Headers],[First]:[Last]]",
+                "Sales[[#Headers],[First]:[Last]]",
+            ),
+            (
+                "Sales[[#Headers], [#Data], [Q'#1]]",
+                "Sales[[#Headers],[#Data],[Q'#1]]",
+            ),
+            ("Sales[[A]:[B]]", "Sales[[A]:[B]]"),
+        ];
+        for (s, display) in cases.iter() {
+            let t = TableRef::parse(s).unwrap_or_else(|| panic!("{}", s));
+            assert_eq!(t.to_string(), *display, "{}", s);
+            assert_eq!(TableRef::parse(display), Some(t), "{}", s);
+        }
+        let t = TableRef::parse("Sales[[#Totals],[Q'#1]:[Q'#4]]").unwrap();
+        assert_eq!(t.table.as_deref(), Some("Sales"));
+        assert_eq!(t.items, vec![TableItem::Totals]);
+        assert_eq!(t.columns, Some(("Q#1".to_string(), "Q#4".to_string())));
+        for s in [
+            "Sales[#Nope]",
+            "Sales[[A],[#Data]]",
+            "Sales[[A]",
+            "Sales[A]x",
+            "A B[C]",
+        ]
+        .iter()
+        {
+            assert_eq!(TableRef::parse(s), None, "{}", s);
+        }
+    }
+}
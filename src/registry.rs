@@ -0,0 +1,142 @@
+use crate::eval::Evaluator;
+use crate::exp::Expression;
+use crate::functions;
+use crate::value::Value;
+use std::collections::HashMap;
+
+pub type EagerFn = dyn Fn(&[Value], &HashMap<String, Value>) -> Value;
+pub type LazyFn = dyn Fn(&[Expression], &Evaluator) -> Value;
+
+pub enum Implementation {
+    // receives the evaluated arguments and the evaluation context; the call is
+    // skipped when an argument evaluates to an error, which becomes the result.
+    Eager(Box<EagerFn>),
+    // receives the unevaluated arguments and evaluates only the ones it needs,
+    // for conditional and error-trapping functions.
+    Lazy(Box<LazyFn>),
+}
+
+pub struct FunctionDef {
+    pub name: String,
+    pub min_args: usize,
+    // None means any number of arguments
+    pub max_args: Option<usize>,
+    implementation: Implementation,
+}
+
+impl FunctionDef {
+    // accepts return whether the function can be called with n arguments.
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min_args && self.max_args.map_or(true, |max| n <= max)
+    }
+
+    pub fn call(&self, arguments: &[Expression], evaluator: &Evaluator) -> Value {
+        match &self.implementation {
+            Implementation::Eager(f) => {
+                let mut args: Vec<Value> = vec![];
+                for arg in arguments.iter() {
+                    let v = evaluator.evaluate(arg);
+                    if v.is_error() {
+                        return v;
+                    }
+                    args.push(v)
+                }
+                f(&args, evaluator.context)
+            }
+            Implementation::Lazy(f) => f(arguments, evaluator),
+        }
+    }
+}
+
+// FunctionRegistry maps upper-cased function names to their definitions. Start
+// from `builtin()` and `register` domain functions on top of it.
+#[derive(Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, FunctionDef>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> FunctionRegistry {
+        FunctionRegistry::default()
+    }
+
+    pub fn builtin() -> FunctionRegistry {
+        let mut r = FunctionRegistry::new();
+        r.register("SUM", 0, None, |args, _| functions::sum(args));
+        r.register("COUNT", 0, None, |args, _| functions::count(args));
+        r.register("AVERAGE", 1, None, |args, _| functions::avarage(args));
+        r.register("POWER", 2, Some(2), |args, _| {
+            functions::broadcast(&args[0], &args[1], functions::power)
+        });
+        r.register("TODAY", 0, Some(0), |args, _| functions::today(args));
+        r.register("CONCAT", 1, None, |args, _| functions::concat_all(args));
+        r.register("CONCATENATE", 1, None, |args, _| {
+            functions::concatenate(args)
+        });
+        r.register("REF", 1, Some(1), functions::get_ref_value);
+        r.register_lazy("IF", 2, Some(3), functions::if_);
+        r.register_lazy("IFS", 2, None, functions::ifs);
+        r.register_lazy("AND", 1, None, functions::and);
+        r.register_lazy("OR", 1, None, functions::or);
+        r.register("NOT", 1, Some(1), |args, _| functions::not(args));
+        r.register("XOR", 1, None, |args, _| functions::xor(args));
+        r.register("TRUE", 0, Some(0), |args, _| functions::true_(args));
+        r.register("FALSE", 0, Some(0), |args, _| functions::false_(args));
+        r.register_lazy("IFERROR", 2, Some(2), functions::iferror);
+        r.register_lazy("CHOOSE", 2, None, functions::choose);
+        r.register_lazy("SWITCH", 3, None, functions::switch);
+        r.register("INDEX", 2, Some(3), |args, _| functions::index(args));
+        r.register("FILTER", 2, Some(3), |args, _| functions::filter(args));
+        r.register("SORT", 1, Some(4), |args, _| functions::sort(args));
+        r.register("SORTBY", 2, None, |args, _| functions::sortby(args));
+        r.register("UNIQUE", 1, Some(3), |args, _| functions::unique(args));
+        r.register("SEQUENCE", 1, Some(4), |args, _| functions::sequence(args));
+        r.register("RANDARRAY", 0, Some(5), |args, _| {
+            functions::randarray(args)
+        });
+        r
+    }
+
+    // register adds a function, replacing any previous one of the same name.
+    pub fn register<F>(&mut self, name: &str, min_args: usize, max_args: Option<usize>, f: F)
+    where
+        F: Fn(&[Value], &HashMap<String, Value>) -> Value + 'static,
+    {
+        self.insert(name, min_args, max_args, Implementation::Eager(Box::new(f)));
+    }
+
+    // register_lazy adds a function which evaluates its arguments itself.
+    pub fn register_lazy<F>(&mut self, name: &str, min_args: usize, max_args: Option<usize>, f: F)
+    where
+        F: Fn(&[Expression], &Evaluator) -> Value + 'static,
+    {
+        self.insert(name, min_args, max_args, Implementation::Lazy(Box::new(f)));
+    }
+
+    fn insert(
+        &mut self,
+        name: &str,
+        min_args: usize,
+        max_args: Option<usize>,
+        implementation: Implementation,
+    ) {
+        let name = name.to_uppercase();
+        self.functions.insert(
+            name.clone(),
+            FunctionDef {
+                name,
+                min_args,
+                max_args,
+                implementation,
+            },
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FunctionDef> {
+        self.functions.get(&name.to_uppercase())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+}
@@ -27,6 +27,26 @@ pub enum TokenSubType {
     Concatenation,
     Intersection,
     Union,
+    Range,
+}
+
+// Span is a half-open range of byte offsets into the formula passed to the parser.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // to_chars converts the byte offsets to char offsets within source.
+    pub fn to_chars(&self, source: &str) -> Span {
+        let count = |i: usize| source.get(..i).map_or(0, |s| s.chars().count());
+        Span::new(count(self.start), count(self.end))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -34,18 +54,21 @@ pub struct Token {
     pub value: String,
     pub token_type: TokenType,
     pub sub_type: TokenSubType,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(v: String, token_type: TokenType, sub_type: TokenSubType) -> Token {
+    pub fn new(v: String, token_type: TokenType, sub_type: TokenSubType, span: Span) -> Token {
         Token {
             value: v,
             token_type,
             sub_type,
+            span,
         }
     }
 }
 
+#[derive(Default)]
 pub struct Tokens {
     index: i64,
     pub items: Vec<Token>,
@@ -58,8 +81,14 @@ impl Tokens {
             items: vec![],
         }
     }
-    pub fn add(&mut self, v: String, token_type: TokenType, sub_type: TokenSubType) -> &Token {
-        let t = Token::new(v, token_type, sub_type);
+    pub fn add(
+        &mut self,
+        v: String,
+        token_type: TokenType,
+        sub_type: TokenSubType,
+        span: Span,
+    ) -> &Token {
+        let t = Token::new(v, token_type, sub_type, span);
         self.add_ref(&t);
         &self.items[self.items.len() - 1]
     }
@@ -92,19 +121,32 @@ impl Tokens {
         true
     }
 
+    // advance provides pub fntion to move the index along one, past the end if
+    // need be.
+    pub fn advance(&mut self) {
+        self.index += 1;
+    }
+
     // current return the current token.
     pub fn current(&self) -> RefCell<Token> {
-        if self.index < 0 {}
         RefCell::new(self.items[self.index as usize].clone())
     }
 
+    // peek return the current token, or None when the index is out of the list.
+    pub fn peek(&self) -> Option<&Token> {
+        if self.index < 0 {
+            return None;
+        }
+        self.items.get(self.index as usize)
+    }
+
     pub fn set_current_field(
         &mut self,
         _value: Option<String>,
         _token_type: Option<TokenType>,
         _sub_type: Option<TokenSubType>,
     ) {
-        let mut token = &mut self.items[self.index as usize];
+        let token = &mut self.items[self.index as usize];
         if let Some(value) = _value {
             token.value = value;
         }
@@ -130,7 +172,7 @@ impl Tokens {
         if self.index < 1 {
             // return None;
         }
-        &self.items[((self.index - 1) as usize)]
+        &self.items[(self.index - 1) as usize]
     }
 
     // push provides pub fntion to push a token onto the stack.
@@ -138,31 +180,20 @@ impl Tokens {
         self.items.push(t);
     }
 
-    // pop provides pub fntion to pop a token off the stack.
+    // pop provides pub fntion to pop a token off the stack, returning the
+    // matching stop token or None when the stack is empty.
     pub fn pop(&mut self) -> Option<Token> {
-        if self.items.is_empty() {
-            return Some(Token {
-                value: String::new(),
-                token_type: TokenType::Function,
-                sub_type: TokenSubType::Stop,
-            });
-        }
-        let t = self.items.pop().unwrap();
-        Some(Token::new(String::new(), t.token_type, TokenSubType::Stop))
-    }
-
-    // token provides pub fntion to non-destructively return the top item on the
-    // stack.
-    pub fn token(&self) -> &Token {
-        &self.items[((self.index - 1) as usize)]
-    }
-
-    // tp return the top token's type.
-    pub fn tp(&self) -> &TokenType {
-        if self.index == -1 {
-            return &TokenType::Noop;
-        }
-        let a: &Token = self.token();
-        &a.token_type
+        let t = self.items.pop()?;
+        Some(Token::new(
+            String::new(),
+            t.token_type,
+            TokenSubType::Stop,
+            t.span,
+        ))
+    }
+
+    // top return the token on top of the stack.
+    pub fn top(&self) -> Option<&Token> {
+        self.items.last()
     }
 }
@@ -1,10 +1,84 @@
+use crate::error::{ErrorKind, ErrorValue};
 use crate::oadate;
+use std::fmt;
 
 pub trait Values: Sized {
     fn to_value(&self) -> Value;
 }
 
-type Array = Vec<Value>;
+// Array is a rectangle of values, like the {1,2;3,4} array constant, stored
+// row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+    pub rows: usize,
+    pub cols: usize,
+    pub items: Vec<Value>,
+}
+
+impl Array {
+    pub fn new(rows: usize, cols: usize, items: Vec<Value>) -> Array {
+        assert_eq!(rows * cols, items.len(), "array items do not fit its shape");
+        Array { rows, cols, items }
+    }
+
+    // row return a single row array.
+    pub fn row(items: Vec<Value>) -> Array {
+        Array::new(1, items.len(), items)
+    }
+
+    // column return a single column array.
+    pub fn column(items: Vec<Value>) -> Array {
+        Array::new(items.len(), 1, items)
+    }
+
+    // from_rows return the array made of rows, which must be of the same length.
+    pub fn from_rows(rows: Vec<Vec<Value>>) -> Array {
+        let cols = rows.first().map_or(0, |r| r.len());
+        Array::new(rows.len(), cols, rows.into_iter().flatten().collect())
+    }
+
+    pub fn iter_rows(&self) -> impl Iterator<Item = &[Value]> {
+        self.items.chunks(self.cols.max(1))
+    }
+
+    // transpose return the array with rows and columns swapped.
+    pub fn transpose(&self) -> Array {
+        let mut items = Vec::with_capacity(self.items.len());
+        for col in 0..self.cols {
+            for row in 0..self.rows {
+                items.push(self.items[row * self.cols + col].clone());
+            }
+        }
+        Array::new(self.cols, self.rows, items)
+    }
+
+    // get return the item at the 0-based row and column.
+    pub fn get(&self, row: usize, col: usize) -> Option<&Value> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        self.items.get(row * self.cols + col)
+    }
+}
+
+// Display writes the array like an array constant.
+impl fmt::Display for Array {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+        for (i, v) in self.items.iter().enumerate() {
+            if i > 0 {
+                f.write_str(if i % self.cols == 0 { ";" } else { "," })?;
+            }
+            match v {
+                Value::String(s) => write!(f, "\"{}\"", s.replace('"', "\"\""))?,
+                Value::Error(e) => write!(f, "{}", e)?,
+                v => f.write_str(&v.to_text().unwrap_or_default())?,
+            }
+        }
+        f.write_str("}")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Boolean(bool),
@@ -13,7 +87,7 @@ pub enum Value {
     Date(f64),
     Empty,
     Unknown,
-    Error(String),
+    Error(ErrorValue),
     Array(Array),
 }
 
@@ -25,6 +99,44 @@ impl Value {
             _ => 0f64,
         }
     }
+    // coerce_number converts an operand of an arithmetic operator like Excel does:
+    // empty is 0, and text must read as a number, a percentage or a date.
+    pub fn coerce_number(&self) -> Result<f64, ErrorValue> {
+        match self {
+            Value::Number(n) | Value::Date(n) => Ok(*n),
+            Value::Boolean(b) => Ok((*b as i8) as f64),
+            Value::Empty => Ok(0f64),
+            Value::String(s) => parse_number(s).ok_or_else(|| ErrorKind::Value.into()),
+            Value::Error(e) => Err(e.clone()),
+            _ => Err(ErrorKind::Value.into()),
+        }
+    }
+    // to_text converts the value like Excel's text operator does: numbers (dates
+    // included) in general format, booleans as TRUE or FALSE and empty as "".
+    pub fn to_text(&self) -> Result<String, ErrorValue> {
+        match self {
+            Value::String(s) => Ok(s.clone()),
+            Value::Number(n) | Value::Date(n) => Ok(format_general(*n)),
+            Value::Boolean(true) => Ok("TRUE".to_string()),
+            Value::Boolean(false) => Ok("FALSE".to_string()),
+            Value::Empty => Ok(String::new()),
+            Value::Error(e) => Err(e.clone()),
+            _ => Err(ErrorKind::Value.into()),
+        }
+    }
+    // to_logical coerces the value like Excel's logical functions do: numbers
+    // are TRUE unless zero, empty is FALSE and text must read TRUE or FALSE.
+    pub fn to_logical(&self) -> Result<bool, ErrorValue> {
+        match self {
+            Value::Boolean(b) => Ok(*b),
+            Value::Number(n) | Value::Date(n) => Ok(*n != 0.0),
+            Value::Empty => Ok(false),
+            Value::String(s) if s.eq_ignore_ascii_case("TRUE") => Ok(true),
+            Value::String(s) if s.eq_ignore_ascii_case("FALSE") => Ok(false),
+            Value::Error(e) => Err(e.clone()),
+            _ => Err(ErrorKind::Value.into()),
+        }
+    }
     pub fn is_number(&self) -> bool {
         matches!(self, Value::Number(_))
     }
@@ -33,7 +145,8 @@ impl Value {
     }
     pub fn as_string(&self) -> String {
         match self {
-            Value::Error(e) => e.clone(),
+            Value::Error(e) => e.to_string(),
+            Value::Array(a) => a.to_string(),
             Value::String(t) => t.clone(),
             Value::Number(f) => format!("{}", f),
             Value::Date(f) => oadate::from_oadate(*f)
@@ -44,6 +157,35 @@ impl Value {
     }
 }
 
+// format_general formats n with at most 15 significant digits, like Excel's
+// General number format.
+fn format_general(n: f64) -> String {
+    if n == 0.0 {
+        return "0".to_string();
+    }
+    let decimals = 14 - n.abs().log10().floor() as i32;
+    if !(0..=30).contains(&decimals) {
+        return format!("{}", n);
+    }
+    let s = format!("{:.*}", decimals as usize, n);
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        s
+    }
+}
+
+// parse_number reads text as a number, a percentage or a date.
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let n = match s.strip_suffix('%') {
+        Some(p) => p.trim_end().parse::<f64>().ok().map(|n| n / 100.0),
+        None => s.parse::<f64>().ok(),
+    };
+    n.filter(|n| n.is_finite())
+        .or_else(|| oadate::parse_date(s))
+}
+
 impl Values for &str {
     fn to_value(&self) -> Value {
         Value::String(self.to_string())
@@ -56,9 +198,15 @@ impl Values for String {
     }
 }
 
+impl Values for ErrorKind {
+    fn to_value(&self) -> Value {
+        Value::Error(ErrorValue::new(*self))
+    }
+}
+
 impl<V: Into<Value>> From<Vec<V>> for Value {
     fn from(val: Vec<V>) -> Value {
-        Value::Array(val.into_iter().map(|v| v.into()).collect())
+        Value::Array(Array::row(val.into_iter().map(|v| v.into()).collect()))
     }
 }
 
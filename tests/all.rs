@@ -7,9 +7,17 @@ mod formula_tests {
     use std::collections::HashMap;
     use std::iter::FromIterator;
 
-    use rexp::eval::Evaluator;
-    use rexp::parser::Parser;
-    use rexp::value::Value;
+    use rexp::error::{ErrorKind, ParseErrorKind};
+    use rexp::eval::{Evaluator, Spill};
+    use rexp::exp::{BinaryOp, Expression, UnaryOp};
+    use rexp::functions;
+    use rexp::parser::{Locale, Parser, ParserOptions};
+    use rexp::reference::{
+        CellRef, RangeRef, ReferenceStyle, Resolver, SheetRef, TableItem, TableRef,
+    };
+    use rexp::registry::FunctionRegistry;
+    use rexp::token::Span;
+    use rexp::value::{Array, Value};
     use test::Bencher;
 
     struct Case {
@@ -17,7 +25,64 @@ mod formula_tests {
         pub context: HashMap<String, Value>,
         pub expect: Value,
     }
+
+    impl Case {
+        // new return a case evaluated without a context.
+        fn new(formula: &'static str, expect: Value) -> Case {
+            Case {
+                formula,
+                context: HashMap::default(),
+                expect,
+            }
+        }
+    }
+
+    // eval parses formula and resolves it.
+    fn eval(
+        formula: &str,
+        functions: &FunctionRegistry,
+        context: &HashMap<String, Value>,
+        resolver: Option<&dyn Resolver>,
+    ) -> Value {
+        let mut p = Parser::new(formula);
+        p.tokenize();
+        evaluator(p.parse().unwrap(), functions, context, resolver).resolve()
+    }
+
+    // evaluator return an evaluator of expr.
+    fn evaluator<'a>(
+        expr: Expression,
+        functions: &'a FunctionRegistry,
+        context: &'a HashMap<String, Value>,
+        resolver: Option<&'a dyn Resolver>,
+    ) -> Evaluator<'a> {
+        Evaluator {
+            expr,
+            context,
+            functions,
+            resolver,
+        }
+    }
+
+    // check evaluates the cases with the builtin functions.
+    fn check(cases: &[Case]) {
+        check_with(cases, &FunctionRegistry::builtin(), None)
+    }
+
+    // check_with evaluates the cases with functions and resolver.
+    fn check_with(cases: &[Case], functions: &FunctionRegistry, resolver: Option<&dyn Resolver>) {
+        for case in cases {
+            assert_eq!(
+                eval(case.formula, functions, &case.context, resolver),
+                case.expect,
+                "{}",
+                case.formula
+            );
+        }
+    }
+
     #[test]
+    #[allow(deprecated)]
     fn eval_all() {
         let empty_context = HashMap::<String, Value>::from_iter(IntoIter::new([(
             "b0987654321".to_string(),
@@ -71,18 +136,1369 @@ mod formula_tests {
                 expect: Value::Empty,
             },
         ];
+        let functions = FunctionRegistry::builtin();
         for case in cases.iter() {
             let mut p = Parser::new(case.formula);
             p.tokenize();
-            let e = p.parse();
+            let e = p.parse().unwrap();
             let evaluator = Evaluator {
                 expr: e,
                 context: &case.context,
+                functions: &functions,
+                resolver: None,
             };
             let result = evaluator.resolve();
             assert_eq!(result, case.expect);
         }
     }
+    #[test]
+    fn custom_functions() {
+        let mut functions = FunctionRegistry::builtin();
+        functions.register("Discount", 2, Some(2), |args, _| {
+            Value::Number(args[0].to_number() * (1.0 - args[1].to_number()))
+        });
+        let cases = [
+            Case::new("DISCOUNT(SUM(50,50),0.25)", Value::Number(75.0)),
+            Case::new("discount(10,0.5)+1", Value::Number(6.0)),
+            Case::new("DISCOUNT(10)", Value::from(ErrorKind::Value)),
+        ];
+        check_with(&cases, &functions, None);
+    }
+
+    #[test]
+    fn error_values() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        let cases: [(&str, ErrorKind, &str); 4] = [
+            ("1/0", ErrorKind::Div0, "#DIV/0!"),
+            ("#N/A", ErrorKind::NA, "#N/A"),
+            ("SUM(1,#spill!)", ErrorKind::Spill, "#SPILL!"),
+            ("REF(\"a1234567890.b0987654321\")", ErrorKind::Ref, "#REF!"),
+        ];
+        for (formula, kind, text) in cases.iter() {
+            let result = eval(formula, &functions, &context, None);
+            assert_eq!(result, Value::from(*kind), "{}", formula);
+            assert_eq!(result.as_string(), *text, "{}", formula);
+        }
+        assert_eq!("#name?".parse::<ErrorKind>(), Ok(ErrorKind::Name));
+        assert_eq!(ErrorKind::Calc.to_string(), "#CALC!");
+    }
+
+    #[test]
+    fn lazy_functions() {
+        let mut functions = FunctionRegistry::builtin();
+        functions.register("BOOM", 0, Some(0), |_, _| panic!("BOOM() evaluated"));
+        functions.register_lazy("FIRST", 1, None, |args, evaluator| {
+            evaluator.evaluate(&args[0])
+        });
+        let cases = [
+            Case::new("IF(0=0, 0, 1/0)", Value::Number(0.0)),
+            Case::new("IF(1=0, BOOM(), 2)", Value::Number(2.0)),
+            Case::new("IF(1=0, 1)", Value::Boolean(false)),
+            Case::new("IFERROR(1/0, 5)", Value::Number(5.0)),
+            Case::new("IFERROR(4, BOOM())", Value::Number(4.0)),
+            Case::new(
+                "IFS(1>2, BOOM(), 2>1, \"b\")",
+                Value::String("b".to_string()),
+            ),
+            Case::new("AND(FALSE, BOOM())", Value::Boolean(false)),
+            Case::new("OR(TRUE, BOOM())", Value::Boolean(true)),
+            Case::new("CHOOSE(2, BOOM(), 7)", Value::Number(7.0)),
+            Case::new("CHOOSE(\"2\", BOOM(), 7)", Value::Number(7.0)),
+            Case::new("CHOOSE(\"two\", BOOM(), 7)", Value::from(ErrorKind::Value)),
+            Case::new(
+                "SWITCH(2, 1, BOOM(), 2, \"two\")",
+                Value::String("two".to_string()),
+            ),
+            Case::new("FIRST(3, BOOM())", Value::Number(3.0)),
+        ];
+        check_with(&cases, &functions, None);
+    }
+
+    #[test]
+    fn logical_functions() {
+        let cases = [
+            // IF
+            Case::new("IF(2, \"yes\", \"no\")", Value::String("yes".to_string())),
+            Case::new("IF(0, \"yes\", \"no\")", Value::String("no".to_string())),
+            Case::new("IF(, \"yes\", \"no\")", Value::String("no".to_string())),
+            Case::new("IF(\"true\", 1, 2)", Value::Number(1.0)),
+            Case::new("IF(\"abc\", 1, 2)", Value::from(ErrorKind::Value)),
+            Case::new("IF(#N/A, 1, 2)", Value::from(ErrorKind::NA)),
+            // IFS
+            Case::new("IFS(FALSE, 1, 0, 2, -1, 3)", Value::Number(3.0)),
+            Case::new("IFS(FALSE, 1)", Value::from(ErrorKind::NA)),
+            Case::new("IFS(\"x\", 1)", Value::from(ErrorKind::Value)),
+            // AND
+            Case::new("AND(TRUE, 1, \"TRUE\")", Value::Boolean(true)),
+            Case::new("AND(TRUE, 0)", Value::Boolean(false)),
+            Case::new("AND(TRUE, \"abc\")", Value::from(ErrorKind::Value)),
+            Case::new("AND(TRUE, 1/0)", Value::from(ErrorKind::Div0)),
+            Case::new("AND({TRUE,FALSE})", Value::Boolean(false)),
+            Case::new("AND({TRUE,\"x\";1,TRUE})", Value::Boolean(true)),
+            Case::new("AND({TRUE,#N/A})", Value::from(ErrorKind::NA)),
+            // OR
+            Case::new("OR(FALSE, 0, -0.5)", Value::Boolean(true)),
+            Case::new("OR(FALSE, )", Value::Boolean(false)),
+            Case::new("OR(\"abc\", TRUE)", Value::from(ErrorKind::Value)),
+            Case::new("OR({FALSE;0}, {1,\"x\"})", Value::Boolean(true)),
+            Case::new("OR({FALSE,0})", Value::Boolean(false)),
+            // NOT
+            Case::new("NOT(TRUE)", Value::Boolean(false)),
+            Case::new("NOT(0)", Value::Boolean(true)),
+            Case::new("NOT(\"abc\")", Value::from(ErrorKind::Value)),
+            // XOR
+            Case::new("XOR(TRUE, TRUE)", Value::Boolean(false)),
+            Case::new("XOR(TRUE, FALSE, 0)", Value::Boolean(true)),
+            Case::new("XOR(1, 1, 1)", Value::Boolean(true)),
+            Case::new("XOR(TRUE, \"x\")", Value::from(ErrorKind::Value)),
+            Case::new("XOR({TRUE,TRUE;TRUE,\"x\"}, FALSE)", Value::Boolean(true)),
+            // SWITCH
+            Case::new("SWITCH(3, 1, \"a\", 2, \"b\")", Value::from(ErrorKind::NA)),
+            Case::new(
+                "SWITCH(3, 1, \"a\", 2, \"b\", \"c\")",
+                Value::String("c".to_string()),
+            ),
+            Case::new("SWITCH(1/0, 1, \"a\")", Value::from(ErrorKind::Div0)),
+            // TRUE, FALSE
+            Case::new("TRUE()", Value::Boolean(true)),
+            Case::new("FALSE()", Value::Boolean(false)),
+            Case::new("true", Value::Boolean(true)),
+            Case::new("IF(FALSE(), 1, TRUE())", Value::Boolean(true)),
+            Case::new("NOT(TRUE()) = FALSE()", Value::Boolean(true)),
+            Case::new("AND(1<2, 2<3)", Value::Boolean(true)),
+        ];
+        check(&cases);
+    }
+
+    #[test]
+    fn arithmetic_coercion() {
+        let context = HashMap::from([("b0987654321".to_string(), Value::Empty)]);
+        let cases = [
+            Case::new("\"50\"+1", Value::Number(51.0)),
+            Case::new("\" 1e3 \"/\"10\"", Value::Number(100.0)),
+            Case::new("\"50%\"*2", Value::Number(1.0)),
+            Case::new("\"2021/02/10\"+1", Value::Number(44238.0)),
+            Case::new("\"2021-02-10\"-\"2021-02-01\"", Value::Number(9.0)),
+            Case::new("TRUE+TRUE", Value::Number(2.0)),
+            Case::new("REF(\"a1234567890.b0987654321\")+1", Value::Number(1.0)),
+            Case::new("\"abc\"*2", Value::from(ErrorKind::Value)),
+            Case::new("\"\"+1", Value::from(ErrorKind::Value)),
+            Case::new("-\"abc\"", Value::from(ErrorKind::Value)),
+            Case::new("1+#N/A", Value::from(ErrorKind::NA)),
+            Case::new("#NUM!/0", Value::from(ErrorKind::Num)),
+            Case::new("\"x\"/0", Value::from(ErrorKind::Value)),
+            Case::new("1e308*10", Value::from(ErrorKind::Num)),
+        ]
+        .map(|case| Case {
+            context: context.clone(),
+            ..case
+        });
+        check(&cases);
+    }
+
+    #[test]
+    fn comparison() {
+        let context = HashMap::from([("b0987654321".to_string(), Value::Empty)]);
+        let cases = [
+            Case::new("\"a\"=\"b\"", Value::Boolean(false)),
+            Case::new("\"a\"=\"A\"", Value::Boolean(true)),
+            Case::new("\"apple\"<\"banana\"", Value::Boolean(true)),
+            Case::new("\"Zebra\">\"apple\"", Value::Boolean(true)),
+            Case::new("\"abc\"<>\"ABC\"", Value::Boolean(false)),
+            Case::new("\"1\"=1", Value::Boolean(false)),
+            Case::new("1<\"0\"", Value::Boolean(true)),
+            Case::new("\"zzz\"<FALSE", Value::Boolean(true)),
+            Case::new("1<TRUE", Value::Boolean(true)),
+            Case::new("FALSE<TRUE", Value::Boolean(true)),
+            Case::new("2>=2", Value::Boolean(true)),
+            Case::new("2<=1", Value::Boolean(false)),
+            Case::new("REF(\"a1234567890.b0987654321\")=0", Value::Boolean(true)),
+            Case::new(
+                "REF(\"a1234567890.b0987654321\")=\"\"",
+                Value::Boolean(true),
+            ),
+            Case::new(
+                "REF(\"a1234567890.b0987654321\")=FALSE",
+                Value::Boolean(true),
+            ),
+            Case::new("REF(\"a1234567890.b0987654321\")<1", Value::Boolean(true)),
+            Case::new(
+                "\"a\">REF(\"a1234567890.b0987654321\")",
+                Value::Boolean(true),
+            ),
+            Case::new(
+                "REF(\"a1234567890.b0987654321\")=REF(\"a1234567890.b0987654321\")",
+                Value::Boolean(true),
+            ),
+            Case::new("1/0=1", Value::from(ErrorKind::Div0)),
+            Case::new("\"a\"<#N/A", Value::from(ErrorKind::NA)),
+        ]
+        .map(|case| Case {
+            context: context.clone(),
+            ..case
+        });
+        check(&cases);
+    }
+
+    #[test]
+    fn concatenation() {
+        let context = HashMap::from([("b0987654321".to_string(), Value::Empty)]);
+        let cases = [
+            Case::new("\"a\"&\"b\"", Value::String("ab".to_string())),
+            Case::new("\"n=\"&1.5", Value::String("n=1.5".to_string())),
+            Case::new("1&2", Value::String("12".to_string())),
+            Case::new("0.1+0.2&\"\"", Value::String("0.3".to_string())),
+            Case::new("1/3&\"\"", Value::String("0.333333333333333".to_string())),
+            Case::new("-2&\"\"", Value::String("-2".to_string())),
+            Case::new("TRUE&\"/\"&FALSE", Value::String("TRUE/FALSE".to_string())),
+            Case::new(
+                "\"[\"&REF(\"a1234567890.b0987654321\")&\"]\"",
+                Value::String("[]".to_string()),
+            ),
+            Case::new("\"a\"&1/0", Value::from(ErrorKind::Div0)),
+            Case::new("\"x\"&1+1", Value::String("x2".to_string())),
+            Case::new(
+                "CONCAT(\"a\", 1, TRUE)",
+                Value::String("a1TRUE".to_string()),
+            ),
+            Case::new(
+                "CONCATENATE(\"a\", \"-\", 2.50)",
+                Value::String("a-2.5".to_string()),
+            ),
+            Case::new(
+                "CONCAT({\"a\",\"b\";1,TRUE}, \"c\")",
+                Value::String("ab1TRUEc".to_string()),
+            ),
+            Case::new("CONCAT({\"a\",#N/A})", Value::from(ErrorKind::NA)),
+            Case::new("CONCATENATE({\"a\",\"b\"})", Value::from(vec!["a", "b"])),
+            Case::new(
+                "CONCATENATE({\"a\";\"b\"}, \"-\", {1,2})",
+                Value::Array(Array::from_rows(vec![
+                    vec!["a-1".into(), "a-2".into()],
+                    vec!["b-1".into(), "b-2".into()],
+                ])),
+            ),
+            Case::new(
+                "CONCATENATE(\"a\", {1,#N/A})",
+                Value::from(vec![Value::from("a1"), Value::from(ErrorKind::NA)]),
+            ),
+        ]
+        .map(|case| Case {
+            context: context.clone(),
+            ..case
+        });
+        check(&cases);
+    }
+
+    #[test]
+    fn exponentiation() {
+        let cases = [
+            Case::new("2^3", Value::Number(8.0)),
+            Case::new("2*3^2", Value::Number(18.0)),
+            Case::new("3^2*2", Value::Number(18.0)),
+            Case::new("2^3^2", Value::Number(64.0)),
+            Case::new("-2^2", Value::Number(4.0)),
+            Case::new("2^-1", Value::Number(0.5)),
+            Case::new("1+2^2-1", Value::Number(4.0)),
+            Case::new("\"4\"^0.5", Value::Number(2.0)),
+            Case::new("POWER(2, 10)", Value::Number(1024.0)),
+            Case::new("(-8)^(1/3)", Value::from(ErrorKind::Num)),
+            Case::new("POWER(0, 0)", Value::from(ErrorKind::Num)),
+            Case::new("0^-1", Value::from(ErrorKind::Div0)),
+        ];
+        check(&cases);
+    }
+
+    #[test]
+    fn percent() {
+        let cases = [
+            Case::new("50%", Value::Number(0.5)),
+            Case::new("200*10%", Value::Number(20.0)),
+            Case::new("10%*200", Value::Number(20.0)),
+            Case::new("-5%", Value::Number(-0.05)),
+            Case::new("(1+1)%", Value::Number(0.02)),
+            Case::new("10%%", Value::Number(0.001)),
+            Case::new("2^200%", Value::Number(4.0)),
+            Case::new("SUM(10%, 1)", Value::Number(1.1)),
+            Case::new("\"x\"%", Value::from(ErrorKind::Value)),
+        ];
+        check(&cases);
+
+        let mut p = Parser::new("-5%");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Unary { operand, span, .. } => {
+                assert_eq!(span, Span::new(0, 3));
+                assert!(matches!(
+                    *operand,
+                    Expression::Unary {
+                        op: UnaryOp::Negate,
+                        ..
+                    }
+                ));
+            }
+            e => panic!("expected percent, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unary_operators() {
+        let cases = [
+            Case::new("--TRUE", Value::Number(1.0)),
+            Case::new("-TRUE", Value::Number(-1.0)),
+            Case::new("-\"x\"", Value::from(ErrorKind::Value)),
+            Case::new("-\"3\"", Value::Number(-3.0)),
+            Case::new("+\"x\"", Value::String("x".to_string())),
+            Case::new("+TRUE", Value::Boolean(true)),
+            Case::new("+-+2", Value::Number(-2.0)),
+            Case::new("1--1", Value::Number(2.0)),
+            Case::new("2*+3", Value::Number(6.0)),
+            Case::new("SUM(-1, +2)", Value::Number(1.0)),
+            Case::new("-#N/A", Value::from(ErrorKind::NA)),
+        ];
+        check(&cases);
+
+        let mut p = Parser::new("+-x");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Unary {
+                op: UnaryOp::Plus,
+                operand,
+                span,
+            } => {
+                assert_eq!(span, Span::new(0, 3));
+                assert!(matches!(
+                    *operand,
+                    Expression::Unary {
+                        op: UnaryOp::Negate,
+                        ..
+                    }
+                ));
+            }
+            e => panic!("expected unary plus, got {:?}", e),
+        }
+    }
+
+    // Sheet is a resolver serving the cells of a small grid; ranges resolve to
+    // their cells in row-major order. Cells of other sheets are 100 times larger,
+    // and a 3D reference sums the sheets it spans.
+    struct Sheet {
+        cells: HashMap<(u32, u32), Value>,
+    }
+
+    impl Sheet {
+        fn scale(sheet: Option<&SheetRef>) -> Option<f64> {
+            match sheet {
+                None => Some(1.0),
+                Some(s) if s.workbook.as_deref() == Some("Missing.xlsx") => None,
+                Some(s) if s.last.is_some() => Some(300.0),
+                Some(_) => Some(100.0),
+            }
+        }
+
+        fn get(&self, sheet: Option<&SheetRef>, row: u32, col: u32) -> Value {
+            match (Sheet::scale(sheet), self.cells.get(&(row, col))) {
+                (None, _) => Value::from(ErrorKind::Ref),
+                (Some(scale), Some(Value::Number(n))) => Value::Number(n * scale),
+                (Some(_), Some(v)) => v.clone(),
+                (_, None) => Value::Empty,
+            }
+        }
+    }
+
+    impl Resolver for Sheet {
+        fn cell(&self, sheet: Option<&SheetRef>, reference: &CellRef) -> Value {
+            self.get(sheet, reference.row.index(), reference.col.index())
+        }
+
+        fn range(&self, sheet: Option<&SheetRef>, range: &RangeRef) -> Value {
+            let (rows, cols) = match range {
+                RangeRef::Area(start, end) => (
+                    (start.row.index(), end.row.index()),
+                    (start.col.index(), end.col.index()),
+                ),
+                RangeRef::Columns(start, end) => ((1, 3), (start.index(), end.index())),
+                RangeRef::Rows(start, end) => ((start.index(), end.index()), (1, 3)),
+            };
+            let mut items = vec![];
+            for row in rows.0..=rows.1 {
+                for col in cols.0..=cols.1 {
+                    items.push(self.get(sheet, row, col));
+                }
+            }
+            let shape = (rows.1 - rows.0 + 1, cols.1 - cols.0 + 1);
+            Value::Array(Array::new(shape.0 as usize, shape.1 as usize, items))
+        }
+    }
+
+    // Orders is a resolver for a table named Orders, with a header row and the
+    // formula on its second data row.
+    struct Orders;
+
+    impl Resolver for Orders {
+        fn cell(&self, _: Option<&SheetRef>, _: &CellRef) -> Value {
+            Value::from(ErrorKind::Ref)
+        }
+
+        fn range(&self, _: Option<&SheetRef>, _: &RangeRef) -> Value {
+            Value::from(ErrorKind::Ref)
+        }
+
+        fn table(&self, reference: &TableRef) -> Value {
+            let names = ["Qty", "Unit Price", "Total"];
+            let rows = [[1.0, 2.5, 2.5], [4.0, 1.0, 4.0], [2.0, 3.0, 6.0]];
+            if reference.table.as_deref().is_some_and(|t| t != "Orders") {
+                return Value::from(ErrorKind::Ref);
+            }
+            let (first, last) = match &reference.columns {
+                Some((first, last)) => (
+                    names.iter().position(|n| n.eq_ignore_ascii_case(first)),
+                    names.iter().position(|n| n.eq_ignore_ascii_case(last)),
+                ),
+                None => (Some(0), Some(names.len() - 1)),
+            };
+            let (first, last) = match (first, last) {
+                (Some(first), Some(last)) => (first, last),
+                _ => return Value::from(ErrorKind::Ref),
+            };
+            let mut items = vec![];
+            if reference.items.contains(&TableItem::Headers) {
+                items.extend(
+                    names[first..=last]
+                        .iter()
+                        .map(|n| Value::String(n.to_string())),
+                );
+            }
+            for (i, row) in rows.iter().enumerate() {
+                if reference.items.contains(&TableItem::ThisRow) && i != 1 {
+                    continue;
+                }
+                if reference.items == [TableItem::Headers] {
+                    break;
+                }
+                items.extend(row[first..=last].iter().map(|n| Value::Number(*n)));
+            }
+            match &items[..] {
+                [item] => item.clone(),
+                _ => Value::from(items),
+            }
+        }
+    }
+
+    #[test]
+    fn references() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        let mut cells = HashMap::new();
+        for row in 1..=3 {
+            for col in 1..=3 {
+                cells.insert((row, col), Value::Number((row * 10 + col) as f64));
+            }
+        }
+        cells.insert((4, 1), Value::from(ErrorKind::NA));
+        let sheet = Sheet { cells };
+        let cases = [
+            Case::new("A1", Value::Number(11.0)),
+            Case::new("$B$3*2", Value::Number(64.0)),
+            Case::new("c$2+$a1", Value::Number(34.0)),
+            Case::new("SUM(A1:A3)", Value::Number(63.0)),
+            Case::new("SUM(B2:C3)", Value::Number(110.0)),
+            Case::new("SUM(A:A)", Value::Number(63.0)),
+            Case::new("SUM($1:$1)", Value::Number(36.0)),
+            Case::new("SUM(A3:A4)", Value::from(ErrorKind::NA)),
+            Case::new("AVERAGE(A4:B4)", Value::from(ErrorKind::NA)),
+            Case::new("COUNT(A1:C3, D4)", Value::Number(9.0)),
+            Case::new("D4", Value::Empty),
+            Case::new("CONCAT(A1:C1, D4)", Value::String("111213".to_string())),
+        ];
+        check_with(&cases, &functions, Some(&sheet));
+
+        let mut p = Parser::new("SUM($A$1:B2)");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Function { arguments, .. } => match &arguments[0] {
+                Expression::Range { range, span, .. } => {
+                    assert_eq!(range.to_string(), "$A$1:B2");
+                    assert_eq!(*span, Span::new(4, 11));
+                }
+                e => panic!("expected a range, got {:?}", e),
+            },
+            e => panic!("expected a function, got {:?}", e),
+        }
+
+        assert_eq!(
+            eval("A1", &functions, &context, None),
+            Value::from(ErrorKind::Ref)
+        );
+    }
+
+    #[test]
+    fn sheet_references() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        let mut cells = HashMap::new();
+        cells.insert((1, 1), Value::Number(1.0));
+        cells.insert((2, 1), Value::Number(2.0));
+        let sheet = Sheet { cells };
+        let cases: [(&str, Value); 7] = [
+            ("Sheet1!A1", Value::Number(100.0)),
+            ("'Q1 Sales'!A1:A9", Value::Number(300.0)),
+            ("SUM('Q1 Sales'!A1:A9)", Value::Number(300.0)),
+            ("'Bob''s data'!A2+A2", Value::Number(202.0)),
+            ("SUM(Sheet1:Sheet3!A1)", Value::Number(300.0)),
+            ("[Book.xlsx]Sheet1!$A$2", Value::Number(200.0)),
+            ("'[Missing.xlsx]Sheet 1'!A1", Value::from(ErrorKind::Ref)),
+        ];
+        for (formula, expect) in cases.iter() {
+            let got = match eval(formula, &functions, &context, Some(&sheet)) {
+                Value::Array(a) => functions::sum(&a.items),
+                v => v,
+            };
+            assert_eq!(got, *expect, "{}", formula);
+        }
+
+        let mut p = Parser::new("= 'Bob''s data'!B2:C3");
+        p.tokenize();
+        let e = p.parse().unwrap();
+        assert_eq!(e.span(), Span::new(2, 21));
+        assert_eq!(e.to_string(), "'Bob''s data'!B2:C3");
+        match e {
+            Expression::Range {
+                sheet: Some(sheet), ..
+            } => {
+                assert_eq!(sheet.first, "Bob's data");
+                assert_eq!(sheet.last, None);
+            }
+            e => panic!("expected a range, got {:?}", e),
+        }
+
+        let mut p = Parser::new("'Q1 Sales!A1");
+        p.tokenize();
+        let err = p.parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedString);
+        assert_eq!((err.span.start, err.span.end), (0, 12));
+    }
+
+    #[test]
+    fn table_references() {
+        let functions = FunctionRegistry::builtin();
+        let cases = [
+            Case::new("SUM(Orders[Qty])", Value::Number(7.0)),
+            Case::new("SUM(Orders[[Qty]:[Total]])", Value::Number(26.0)),
+            Case::new("[@Qty]*[@[Unit Price]]", Value::Number(4.0)),
+            Case::new("Orders[[#This Row],[Total]]", Value::Number(4.0)),
+            Case::new("COUNT(Orders[])", Value::Number(9.0)),
+            Case::new(
+                "COUNT(Orders[[#Headers],[Qty]:[Total]])",
+                Value::Number(0.0),
+            ),
+            Case::new(
+                "CONCAT(Orders[[#Headers],[Unit Price]])",
+                Value::String("Unit Price".to_string()),
+            ),
+            Case::new("SUM(Orders[Discount])", Value::from(ErrorKind::Ref)),
+            Case::new("SUM(Returns[Qty])", Value::from(ErrorKind::Ref)),
+        ];
+        check_with(&cases, &functions, Some(&Orders));
+
+        let mut p = Parser::new("Orders[[#Totals],[Qty]] + 1");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Binary { left, .. } => match *left {
+                Expression::Table { reference, span } => {
+                    assert_eq!(reference.items, vec![TableItem::Totals]);
+                    assert_eq!(span, Span::new(0, 23));
+                }
+                e => panic!("expected a table reference, got {:?}", e),
+            },
+            e => panic!("expected an operator, got {:?}", e),
+        }
+
+        let mut p = Parser::new("SUM(Orders[[Qty],[Total])");
+        p.tokenize();
+        let err = p.parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnbalancedParen);
+    }
+
+    #[test]
+    fn r1c1_references() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        let mut cells = HashMap::new();
+        for row in 1..=3 {
+            for col in 1..=3 {
+                cells.insert((row, col), Value::Number((row * 10 + col) as f64));
+            }
+        }
+        let sheet = Sheet { cells };
+        let options = ParserOptions {
+            reference_style: ReferenceStyle::R1C1,
+            ..ParserOptions::default()
+        };
+        // a running total stored once in R1C1, instantiated for rows 2 and 3 of
+        // column C
+        let formula = "R[-1]C+SUM(RC1:RC[-1])";
+        let cases = [
+            ("C2", "C1", "$A2:B2", 13.0 + 21.0 + 22.0),
+            ("C3", "C2", "$A3:B3", 23.0 + 31.0 + 32.0),
+        ];
+        for (cell, above, range, expect) in cases.iter() {
+            let mut p = Parser::with_options(formula, options.clone());
+            p.tokenize();
+            let e = p.parse().unwrap();
+            let anchor = CellRef::parse(cell).unwrap();
+            let e = e.to_a1(&anchor);
+            match &e {
+                Expression::Binary { left, right, .. } => {
+                    assert_eq!(left.to_string(), *above);
+                    match &**right {
+                        Expression::Function { arguments, .. } => {
+                            assert_eq!(arguments[0].to_string(), *range)
+                        }
+                        e => panic!("expected a function, got {:?}", e),
+                    }
+                }
+                e => panic!("expected an operator, got {:?}", e),
+            }
+            assert_eq!(
+                evaluator(e, &functions, &context, Some(&sheet)).resolve(),
+                Value::Number(*expect),
+                "{}",
+                cell
+            );
+        }
+
+        // and back, from the A1 formula of C3
+        let mut p = Parser::new("C2+SUM($A3:B3)");
+        p.tokenize();
+        let e = p.parse().unwrap().to_r1c1(&CellRef::parse("C3").unwrap());
+        match &e {
+            Expression::Binary { left, right, .. } => {
+                assert_eq!(left.to_string(), "R[-1]C");
+                match &**right {
+                    Expression::Function { arguments, .. } => {
+                        assert_eq!(arguments[0].to_string(), "RC1:RC[-1]")
+                    }
+                    e => panic!("expected a function, got {:?}", e),
+                }
+            }
+            e => panic!("expected an operator, got {:?}", e),
+        }
+
+        // whole formulas, stored as R1C1 text and instantiated one cell down and
+        // right of where they were written
+        let cases = [
+            (
+                "IF(B2>0,SUM($A2:B2)*-(1-B1%),\"a\"\"b\")",
+                "IF(R[-1]C[-1]>0,SUM(R[-1]C1:R[-1]C[-1])*-(1-R[-2]C[-1]%),\"a\"\"b\")",
+                "IF(C3>0,SUM($A3:C3)*-(1-C2%),\"a\"\"b\")",
+            ),
+            (
+                "1-(2-A1)^(1+1)&'Q1 Sales'!B2#",
+                "1-(2-R[-2]C[-2])^(1+1)&'Q1 Sales'!R[-1]C[-1]#",
+                "1-(2-B2)^(1+1)&'Q1 Sales'!C3#",
+            ),
+            ("$A$1", "R1C1", "$A$1"),
+            ("SUM($A:$A)", "SUM(C1:C1)", "SUM($A:$A)"),
+            ("SUM($1:$2)", "SUM(R1:R2)", "SUM($1:$2)"),
+            ("Sheet1!$B$2+B$2", "Sheet1!R2C2+R2C[-1]", "Sheet1!$B$2+C$2"),
+            (
+                "INDEX({1,-2;\"x\",FALSE},2,)+#N/A",
+                "INDEX({1,-2;\"x\",FALSE},2,)+#N/A",
+                "INDEX({1,-2;\"x\",FALSE},2,)+#N/A",
+            ),
+        ];
+        for (a1, r1c1, moved) in cases.iter() {
+            let mut p = Parser::new(a1);
+            p.tokenize();
+            let stored = p
+                .parse()
+                .unwrap()
+                .to_r1c1(&CellRef::parse("C3").unwrap())
+                .to_string();
+            assert_eq!(stored, *r1c1);
+            let mut p = Parser::with_options(&stored, options.clone());
+            p.tokenize();
+            let e = p.parse().unwrap().to_a1(&CellRef::parse("D4").unwrap());
+            assert_eq!(e.to_string(), *moved);
+        }
+
+        // unanchored R1C1 references and references off the sheet are #REF!
+        for (formula, anchor) in [("R[-1]C", None), ("R[-1]C", Some("A1")), ("A1", None)].iter() {
+            let mut p = Parser::with_options(formula, options.clone());
+            p.tokenize();
+            let mut e = p.parse().unwrap();
+            if let Some(anchor) = anchor {
+                e = e.to_a1(&CellRef::parse(anchor).unwrap());
+            }
+            let expect = match formula {
+                // A1 is a name in R1C1 notation
+                &"A1" => Value::String("A1".to_string()),
+                _ => Value::from(ErrorKind::Ref),
+            };
+            assert_eq!(
+                evaluator(e, &functions, &context, Some(&sheet)).resolve(),
+                expect,
+                "{}",
+                formula
+            );
+        }
+    }
+
+    #[test]
+    fn array_constants() {
+        let grid = Value::Array(Array::new(
+            2,
+            3,
+            vec![
+                Value::Number(1.0),
+                Value::Number(2.0),
+                Value::Number(3.0),
+                Value::Number(4.0),
+                Value::Number(5.0),
+                Value::Number(6.0),
+            ],
+        ));
+        let cases = [
+            Case::new("{1,2,3;4,5,6}", grid),
+            Case::new("SUM({1,2,3})", Value::Number(6.0)),
+            Case::new("SUM({1,2;3,4})", Value::Number(10.0)),
+            Case::new("SUM({1,#N/A})", Value::from(ErrorKind::NA)),
+            Case::new("AVERAGE({1,#N/A})", Value::from(ErrorKind::NA)),
+            Case::new("AVERAGE({1,2;3,6})", Value::Number(3.0)),
+            Case::new("COUNT({1,\"a\";TRUE,-2.5})", Value::Number(2.0)),
+            Case::new(
+                "{1;2;3}",
+                Value::Array(Array::column(vec![1.into(), 2.into(), 3.into()])),
+            ),
+            Case::new(
+                "{\"a\",TRUE,#N/A}",
+                Value::from(vec![
+                    Value::from("a"),
+                    Value::from(true),
+                    Value::from(ErrorKind::NA),
+                ]),
+            ),
+            Case::new("INDEX({1,2;3,4},2,1)", Value::Number(3.0)),
+            Case::new("INDEX({1,2,3},2)", Value::Number(2.0)),
+            Case::new("INDEX({1;2;3},3)", Value::Number(3.0)),
+            Case::new(
+                "INDEX({1,2;3,4},0,2)",
+                Value::Array(Array::column(vec![2.into(), 4.into()])),
+            ),
+            Case::new("INDEX({1,2;3,4},1,0)", Value::from(vec![1, 2])),
+            Case::new("INDEX({1,2;3,4},3,1)", Value::from(ErrorKind::Ref)),
+            Case::new("INDEX({1,2;3,4},-1,1)", Value::from(ErrorKind::Value)),
+            Case::new("INDEX(7,1,1)", Value::Number(7.0)),
+        ];
+        check(&cases);
+
+        let mut p = Parser::new("1+{1,-2;\"x\",FALSE}");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Binary { right, .. } => {
+                assert_eq!(right.span(), Span::new(2, 18));
+                assert_eq!(right.to_string(), "{1,-2;\"x\",FALSE}");
+            }
+            e => panic!("expected an operator, got {:?}", e),
+        }
+
+        let errors: [(&str, ParseErrorKind, usize, usize); 6] = [
+            ("{1,2;3}", ParseErrorKind::RaggedArray, 4, 7),
+            ("{1;2,3}", ParseErrorKind::RaggedArray, 2, 7),
+            ("{1,,2}", ParseErrorKind::UnexpectedToken, 3, 3),
+            ("{1+1}", ParseErrorKind::UnexpectedToken, 1, 4),
+            ("{SUM(1)}", ParseErrorKind::UnexpectedToken, 1, 7),
+            ("{1,2", ParseErrorKind::UnbalancedParen, 0, 1),
+        ];
+        for (formula, kind, start, end) in errors.iter() {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            let err = p.parse().unwrap_err();
+            assert_eq!(err.kind, *kind, "{}", formula);
+            assert_eq!(
+                (err.span.start, err.span.end),
+                (*start, *end),
+                "{}",
+                formula
+            );
+        }
+    }
+
+    #[test]
+    fn array_broadcasting() {
+        let na = || Value::from(ErrorKind::NA);
+        let cases = [
+            Case::new("{1,2,3}*2", Value::from(vec![2, 4, 6])),
+            Case::new("10-{1,2,3}", Value::from(vec![9, 8, 7])),
+            Case::new("{1,2,3}+{10,20,30}", Value::from(vec![11, 22, 33])),
+            Case::new(
+                "{1,2}+{10;20}",
+                Value::Array(Array::new(
+                    2,
+                    2,
+                    vec![11.into(), 12.into(), 21.into(), 22.into()],
+                )),
+            ),
+            Case::new(
+                "{1,2,3}+{10,20}",
+                Value::from(vec![11.into(), 22.into(), na()]),
+            ),
+            Case::new(
+                "{1,2;3,4}*{1,2,3}",
+                Value::Array(Array::new(
+                    2,
+                    3,
+                    vec![1.into(), 4.into(), na(), 3.into(), 8.into(), na()],
+                )),
+            ),
+            Case::new("{2,3}^2", Value::from(vec![4, 9])),
+            Case::new("POWER(2,{1,2})", Value::from(vec![2, 4])),
+            Case::new("\"x\"&{1,\"y\"}", Value::from(vec!["x1", "xy"])),
+            Case::new("{1,2,3}={1,5,3}", Value::from(vec![true, false, true])),
+            Case::new(
+                "-{1,-2}%",
+                Value::from(vec![Value::Number(-0.01), Value::Number(0.02)]),
+            ),
+            Case::new("SUM({1,2,3}*{4,5,6})", Value::Number(32.0)),
+            Case::new("SUM({1,2}/0)", Value::from(ErrorKind::Div0)),
+        ];
+        check(&cases);
+    }
+
+    #[test]
+    fn dynamic_arrays() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::from([(
+            "b0987654321".to_string(),
+            Value::Array(Array::column(vec![
+                3.into(),
+                1.into(),
+                4.into(),
+                1.into(),
+                5.into(),
+            ])),
+        )]);
+        let column = |items: Vec<Value>| Value::Array(Array::column(items));
+        let grid = |rows: usize, cols: usize, items: Vec<i32>| {
+            Value::Array(Array::new(
+                rows,
+                cols,
+                items.into_iter().map(Value::from).collect(),
+            ))
+        };
+        let sorted = |items: Vec<Value>| Value::Array(Array::new(3, 2, items));
+        let cases = [
+            Case::new(
+                "FILTER({1,2;3,4;5,6},{TRUE;FALSE;TRUE})",
+                grid(2, 2, vec![1, 2, 5, 6]),
+            ),
+            Case::new(
+                "FILTER({1,2,3;4,5,6},{1,0,1})",
+                grid(2, 2, vec![1, 3, 4, 6]),
+            ),
+            Case::new("FILTER({1,2,3},{1,2,3}>5)", Value::from(ErrorKind::Calc)),
+            Case::new("FILTER({1,2,3},{1,2,3}>5,\"none\")", Value::from("none")),
+            Case::new(
+                "FILTER({1,2,3},{TRUE,FALSE})",
+                Value::from(ErrorKind::Value),
+            ),
+            Case::new("SORT({3;1;2})", column(vec![1.into(), 2.into(), 3.into()])),
+            Case::new(
+                "SORT({\"b\",2;\"a\",3;\"C\",1})",
+                sorted(vec![
+                    "a".into(),
+                    3.into(),
+                    "b".into(),
+                    2.into(),
+                    "C".into(),
+                    1.into(),
+                ]),
+            ),
+            Case::new(
+                "SORT({\"b\",2;\"a\",3;\"C\",1},1,-1)",
+                sorted(vec![
+                    "C".into(),
+                    1.into(),
+                    "b".into(),
+                    2.into(),
+                    "a".into(),
+                    3.into(),
+                ]),
+            ),
+            Case::new("SORT({3,1,2},1,1,TRUE)", Value::from(vec![1, 2, 3])),
+            Case::new("SORT({3;1;2},2)", Value::from(ErrorKind::Value)),
+            Case::new("SORT({3;1;2},1,0)", Value::from(ErrorKind::Value)),
+            Case::new(
+                "SORTBY({\"a\";\"b\";\"c\"},{2;3;1})",
+                column(vec!["c".into(), "a".into(), "b".into()]),
+            ),
+            Case::new(
+                "SORTBY({1;2;3;4},{1;2;1;2},-1,{4;3;2;1},1)",
+                column(vec![4.into(), 2.into(), 3.into(), 1.into()]),
+            ),
+            Case::new("SORTBY({1,2,3},{3,2,1})", Value::from(vec![3, 2, 1])),
+            Case::new("SORTBY({1;2;3},{1,2,3})", Value::from(ErrorKind::Value)),
+            Case::new(
+                "UNIQUE({1;2;1;\"a\";\"A\"})",
+                column(vec![1.into(), 2.into(), "a".into()]),
+            ),
+            Case::new("UNIQUE({1,2;1,2;3,4})", grid(2, 2, vec![1, 2, 3, 4])),
+            Case::new("UNIQUE({1;2;1},FALSE,TRUE)", column(vec![2.into()])),
+            Case::new("SEQUENCE(2,3,10,-1)", grid(2, 3, vec![10, 9, 8, 7, 6, 5])),
+            Case::new("SUM(SEQUENCE(100))", Value::Number(5050.0)),
+        ]
+        .map(|case| Case {
+            context: context.clone(),
+            ..case
+        });
+        check(&cases);
+
+        // filter and sort a REF column
+        let column_ref = "REF(\"a1234567890.b0987654321\")";
+        let formula = format!("SORT(FILTER({0},{0}<4),1,-1)", column_ref);
+        assert_eq!(
+            eval(&formula, &functions, &context, None),
+            column(vec![3.into(), 1.into(), 1.into()])
+        );
+
+        for (formula, integer) in [
+            ("RANDARRAY(3,2,5,10)", false),
+            ("RANDARRAY(3,2,5,10,TRUE)", true),
+        ]
+        .iter()
+        {
+            match eval(formula, &functions, &context, None) {
+                Value::Array(a) => {
+                    assert_eq!((a.rows, a.cols), (3, 2));
+                    for v in a.items.iter() {
+                        let n = v.to_number();
+                        assert!((5.0..=10.0).contains(&n), "{}", n);
+                        if *integer {
+                            assert_eq!(n.fract(), 0.0);
+                        }
+                    }
+                }
+                v => panic!("expected an array, got {:?}", v),
+            }
+        }
+        let cases = [
+            ("SEQUENCE(0)", ErrorKind::Calc),
+            ("RANDARRAY(2,0)", ErrorKind::Calc),
+            ("SEQUENCE(1048576,16384)", ErrorKind::Num),
+            ("RANDARRAY(1048576,2)", ErrorKind::Num),
+        ];
+        for (formula, kind) in cases.iter() {
+            assert_eq!(
+                eval(formula, &functions, &context, None),
+                Value::from(*kind),
+                "{}",
+                formula
+            );
+        }
+    }
+
+    // Spilled is a resolver for a grid holding the spilled array of a formula.
+    struct Spilled {
+        anchor: CellRef,
+        spill: Spill,
+    }
+
+    impl Resolver for Spilled {
+        fn cell(&self, _: Option<&SheetRef>, reference: &CellRef) -> Value {
+            let row = reference.row.index() as usize;
+            let col = reference.col.index() as usize;
+            let (top, left) = (
+                self.anchor.row.index() as usize,
+                self.anchor.col.index() as usize,
+            );
+            match &self.spill.value {
+                Value::Array(a) if row >= top && col >= left => a
+                    .get(row - top, col - left)
+                    .cloned()
+                    .unwrap_or(Value::Empty),
+                _ => Value::Empty,
+            }
+        }
+
+        fn range(&self, _: Option<&SheetRef>, _: &RangeRef) -> Value {
+            Value::from(ErrorKind::Ref)
+        }
+
+        fn spill(&self, _: Option<&SheetRef>, anchor: &CellRef) -> Value {
+            if (anchor.row.index(), anchor.col.index())
+                != (self.anchor.row.index(), self.anchor.col.index())
+            {
+                return Value::from(ErrorKind::Ref);
+            }
+            self.spill.value.clone()
+        }
+    }
+
+    #[test]
+    fn spill_ranges() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        let anchor = CellRef::parse("B2").unwrap();
+        let evaluate = |formula: &str| {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            evaluator(p.parse().unwrap(), &functions, &context, None)
+        };
+
+        let spill = evaluate("SEQUENCE(3,2)").resolve_spill(&anchor, |c| c.to_string() == "C4");
+        assert_eq!(spill.value, Value::from(ErrorKind::Spill));
+        assert_eq!(spill.extent.unwrap().to_string(), "B2:C4");
+        assert_eq!(spill.blocked, vec![CellRef::parse("C4").unwrap()]);
+
+        // the anchor cell holds the formula itself
+        let spill = evaluate("SEQUENCE(3,2)").resolve_spill(&anchor, |c| c.to_string() == "B2");
+        assert_eq!(spill.extent.unwrap().to_string(), "B2:C4");
+        assert!(spill.blocked.is_empty());
+
+        let spill = evaluate("1+1").resolve_spill(&anchor, |_| true);
+        assert_eq!(spill.value, Value::Number(2.0));
+        assert_eq!(spill.extent, None);
+
+        let spill =
+            evaluate("SEQUENCE(2)").resolve_spill(&CellRef::parse("A1048576").unwrap(), |_| false);
+        assert_eq!(spill.value, Value::from(ErrorKind::Spill));
+        assert_eq!(spill.extent, None);
+
+        // an anchor with R1C1 offsets locates no cell
+        let spill = evaluate("SEQUENCE(2)")
+            .resolve_spill(&CellRef::parse_r1c1("R[1]C").unwrap(), |_| false);
+        assert_eq!(spill.value, Value::from(ErrorKind::Ref));
+        assert_eq!(spill.extent, None);
+
+        let empty = Expression::Literal {
+            value: Value::Array(Array::new(0, 2, vec![])),
+            span: Span::new(0, 0),
+        };
+        let spill = evaluator(empty, &functions, &context, None).resolve_spill(&anchor, |_| false);
+        assert_eq!(spill.value, Value::from(ErrorKind::Calc));
+        assert_eq!(spill.extent, None);
+
+        let host = Spilled {
+            anchor,
+            spill: evaluate("SEQUENCE(3,2)").resolve_spill(&anchor, |_| false),
+        };
+        let cases = [
+            Case::new("SUM(B2#)", Value::Number(21.0)),
+            Case::new("SUM($B$2#*10)", Value::Number(210.0)),
+            Case::new("C3", Value::Number(4.0)),
+            Case::new("INDEX(B2#,3,2)", Value::Number(6.0)),
+            Case::new("SUM(C3#)", Value::from(ErrorKind::Ref)),
+        ];
+        check_with(&cases, &functions, Some(&host));
+
+        let mut p = Parser::new("'Q1 Sales'!A1#%");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Unary { operand, .. } => {
+                assert_eq!(operand.to_string(), "'Q1 Sales'!A1#");
+                assert_eq!(operand.span(), Span::new(0, 14));
+            }
+            e => panic!("expected a percent, got {:?}", e),
+        }
+        for formula in ["1#", "\"a\"#", "SUM(A1:B2#)"].iter() {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            let err = p.parse().unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::UnexpectedToken, "{}", formula);
+        }
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let cases = [
+            Case::new("\"He said \"\"hi\"\"\"", Value::from("He said \"hi\"")),
+            Case::new("\"\"\"\"", Value::from("\"")),
+            Case::new("\"\"", Value::from("")),
+            Case::new("\"a\"\"\"&\"b\"", Value::from("a\"b")),
+            Case::new("CONCAT(\"\"\"\",\"x\",\"\"\"\")", Value::from("\"x\"")),
+        ];
+        check(&cases);
+
+        let mut p = Parser::new("\"a\"\"b\"");
+        p.tokenize();
+        assert_eq!(p.parse().unwrap().span(), Span::new(0, 6));
+
+        let mut p = Parser::new("\"a\"\"");
+        p.tokenize();
+        let err = p.parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedString);
+    }
+
+    #[test]
+    fn render_round_trip() {
+        let formulas = [
+            "\"He said \"\"hi\"\"\"",
+            "IF(A1>0,\"\"\"\",\"x\")",
+            "SUM(1,2)*-3%",
+            "CONCAT(\"a\",TODAY())",
+            "(1+2)^2",
+            "{1,\"a\"\"b\";-2,TRUE}",
+            "SUM('Q1 Sales'!B2:B9,Table1[[#Headers],[A]])",
+            "B2#",
+            "#N/A",
+        ];
+        for formula in formulas.iter() {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            p.parse().unwrap();
+            assert_eq!(p.render(), *formula);
+        }
+    }
+
+    #[test]
+    fn unicode_formulas() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::from([("größe".to_string(), Value::Number(3.0))]);
+        let mut cells = HashMap::new();
+        cells.insert((1, 1), Value::Number(1.0));
+        let sheet = Sheet { cells };
+        let cases = [
+            Case::new("\"日本\"", Value::from("日本")),
+            Case::new("CONCAT(\"café\",\"🍰\")", Value::from("café🍰")),
+            Case::new("\"«\"\"»\"\"\"&\"é\"", Value::from("«\"»\"é")),
+            Case::new("IF(\"ä\"=\"ä\",\"ja\",\"nein\")", Value::from("ja")),
+            Case::new("REF(\"a1234567890.größe\")*2", Value::Number(6.0)),
+            Case::new("日本!A1", Value::Number(100.0)),
+            Case::new("'Ventes été'!A1+A1", Value::Number(101.0)),
+        ]
+        .map(|case| Case {
+            context: context.clone(),
+            ..case
+        });
+        check_with(&cases, &functions, Some(&sheet));
+
+        let mut p = Parser::new("SUM(Données[[Qté]:[Coût €]])");
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Function { arguments, .. } => match &arguments[0] {
+                Expression::Table { reference, span } => {
+                    assert_eq!(reference.table.as_deref(), Some("Données"));
+                    assert_eq!(
+                        reference.columns,
+                        Some(("Qté".to_string(), "Coût €".to_string()))
+                    );
+                    assert_eq!(*span, Span::new(4, 32));
+                }
+                e => panic!("expected a table reference, got {:?}", e),
+            },
+            e => panic!("expected a function, got {:?}", e),
+        }
+
+        let mut p = Parser::new("\"日本\"+)");
+        p.tokenize();
+        let err = p.parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnbalancedParen);
+        assert_eq!((err.span.start, err.span.end), (9, 10));
+        assert_eq!((err.char_span.start, err.char_span.end), (5, 6));
+
+        let formula = "CONCAT('Ventes été'!A1,\"ü\"\"ß\",Données[@Qté])";
+        let mut p = Parser::new(formula);
+        p.tokenize();
+        p.parse().unwrap();
+        assert_eq!(p.render(), formula);
+    }
+
+    #[test]
+    fn locale_separators() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        let options = ParserOptions {
+            locale: Locale::DE_DE,
+            ..ParserOptions::default()
+        };
+        let cases: [(&str, Value); 10] = [
+            ("=SUM(1,5;2,5)", Value::Number(4.0)),
+            ("1,5E1+,5", Value::Number(15.5)),
+            ("1.5", Value::from("1.5")),
+            ("=nan", Value::from("nan")),
+            ("-1,5*2", Value::Number(-3.0)),
+            ("SUM({1.2;3.4})", Value::Number(10.0)),
+            ("INDEX({1,5.2;3.4,5};2;2)", Value::Number(4.5)),
+            ("IF(0,5>0;\"ja\";\"nein\")", Value::from("ja")),
+            ("CONCAT(\"a,b\";\"c;d\")", Value::from("a,bc;d")),
+            ("50%+0,25", Value::Number(0.75)),
+        ];
+        for (formula, expect) in cases.iter() {
+            let mut p = Parser::with_options(formula, options.clone());
+            p.tokenize();
+            assert_eq!(
+                evaluator(p.parse().unwrap(), &functions, &context, None).resolve(),
+                *expect,
+                "{}",
+                formula
+            );
+        }
+
+        let mut p = Parser::with_options("SUM(1,5;2,5)", options.clone());
+        p.tokenize();
+        match p.parse().unwrap() {
+            Expression::Function { arguments, .. } => {
+                assert_eq!(arguments[0].span(), Span::new(4, 7));
+                assert_eq!(arguments[1].span(), Span::new(8, 11));
+            }
+            e => panic!("expected a function, got {:?}", e),
+        }
+
+        // translation between locales
+        let cases = [
+            (
+                "SUM(1,5;{1.2;3,5.-4};'Q1, Sales'!A1)",
+                "SUM(1.5,{1,2;3.5,-4},'Q1, Sales'!A1)",
+            ),
+            (
+                "IF(A1>0,5;\"1,5;2\";(B1+0,1)%)",
+                "IF(A1>0.5,\"1,5;2\",(B1+0.1)%)",
+            ),
+        ];
+        for (de, en) in cases.iter() {
+            let mut p = Parser::with_options(de, options.clone());
+            p.tokenize();
+            p.parse().unwrap();
+            assert_eq!(p.render(), *de);
+            assert_eq!(p.render_locale(&Locale::EN_US), *en);
+
+            let mut p = Parser::new(en);
+            p.tokenize();
+            p.parse().unwrap();
+            assert_eq!(p.render_locale(&Locale::DE_DE), *de);
+        }
+    }
+
+    #[test]
+    fn tokenize_twice() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        for formula in ["1+1", "SUM({1,2;3,4})"].iter() {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            let tokens = p.pretty();
+            p.tokenize();
+            assert_eq!(p.pretty(), tokens, "{}", formula);
+            assert_eq!(p.render(), *formula);
+        }
+
+        let mut p = Parser::new("\"a");
+        p.tokenize();
+        p.tokenize();
+        let err = p.parse().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedString);
+
+        let mut p = Parser::new("1+1");
+        p.tokenize();
+        p.tokenize();
+        assert_eq!(
+            evaluator(p.parse().unwrap(), &functions, &context, None).resolve(),
+            Value::Number(2.0)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases: [(&str, ParseErrorKind, usize, usize); 14] = [
+            ("SUM(1,", ParseErrorKind::UnbalancedParen, 0, 4),
+            ("(1+2", ParseErrorKind::UnbalancedParen, 0, 1),
+            ("1+2)", ParseErrorKind::UnbalancedParen, 3, 4),
+            ("1+", ParseErrorKind::DanglingOperator, 1, 2),
+            ("=-", ParseErrorKind::DanglingOperator, 1, 2),
+            ("\"abc", ParseErrorKind::UnterminatedString, 0, 4),
+            ("1*/2", ParseErrorKind::UnexpectedToken, 2, 3),
+            ("  SUM(1 2)", ParseErrorKind::UnsupportedOperator, 7, 8),
+            ("(1,2)", ParseErrorKind::UnsupportedOperator, 2, 3),
+            ("Sheet1!", ParseErrorKind::UnexpectedToken, 0, 7),
+            ("'abc'!", ParseErrorKind::UnexpectedToken, 0, 6),
+            ("'abc'!1", ParseErrorKind::UnexpectedToken, 0, 7),
+            ("!A1", ParseErrorKind::UnexpectedToken, 0, 3),
+            ("'abc'", ParseErrorKind::UnexpectedToken, 0, 5),
+        ];
+        for (formula, kind, start, end) in cases.iter() {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            let err = p.parse().unwrap_err();
+            assert_eq!(err.kind, *kind, "{}", formula);
+            assert_eq!(
+                (err.span.start, err.span.end),
+                (*start, *end),
+                "{}",
+                formula
+            );
+        }
+    }
+
+    #[test]
+    fn expression_spans() {
+        let formula = "= SUM(1, \"a\") * -2";
+        let mut p = Parser::new(formula);
+        p.tokenize();
+        let e = p.parse().unwrap();
+        assert_eq!(e.span(), Span::new(2, 18));
+        match e {
+            Expression::Binary {
+                op: BinaryOp::Multiply,
+                left,
+                right,
+                ..
+            } => {
+                assert_eq!(left.span(), Span::new(2, 13));
+                assert_eq!(&formula[16..18], "-2");
+                assert_eq!(right.span(), Span::new(16, 18));
+                match *left {
+                    Expression::Function { arguments, .. } => {
+                        assert_eq!(arguments[0].span(), Span::new(6, 7));
+                        assert_eq!(arguments[1].span(), Span::new(9, 12));
+                    }
+                    _ => panic!("expected a function"),
+                }
+            }
+            _ => panic!("expected an operator"),
+        }
+    }
+
+    #[test]
+    fn unknown_functions() {
+        let functions = FunctionRegistry::builtin();
+        let context = HashMap::default();
+        for formula in ["SUMM(1,2)", "FOO(3)", "SUM(1,BAR())"].iter() {
+            let mut p = Parser::new(formula);
+            p.tokenize();
+            let e = p.parse().unwrap();
+            let err = p.validate(&e, &functions).unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::UnknownFunction, "{}", formula);
+            assert_eq!(
+                evaluator(e, &functions, &context, None).resolve(),
+                Value::from(ErrorKind::Name),
+                "{}",
+                formula
+            );
+        }
+
+        let mut p = Parser::new("IF(TRUE)");
+        p.tokenize();
+        let e = p.parse().unwrap();
+        let err = p.validate(&e, &functions).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::ArgumentCount);
+
+        let mut p = Parser::new("SUM(1)+TODAY(1)");
+        p.tokenize();
+        let e = p.parse().unwrap();
+        let err = p.validate(&e, &functions).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::ArgumentCount);
+        assert_eq!((err.span.start, err.span.end), (7, 15));
+
+        // resolve leaves unknown functions to the evaluation
+        assert_eq!(rexp::resolve("IFERROR(FOO(1),0)").unwrap(), "0");
+        assert_eq!(rexp::resolve("FOO(1)").unwrap(), "#NAME?");
+        let err = rexp::validate("FOO(1)").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnknownFunction);
+        assert!(rexp::validate("IFERROR(SUM(1),0)").is_ok());
+    }
+
     // cargo test -- formula_tests   --bench
     // don't know why cargo bench won't run this
     #[bench]